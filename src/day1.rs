@@ -1,6 +1,15 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use winnow::combinator::repeat;
+use pathfinding::{kuhn_munkres::kuhn_munkres_min, matrix::Matrix};
+use serde::Serialize;
+
+use winnow::combinator::{repeat, separated};
 use winnow::{
     ascii::space1,
     combinator::{opt, seq, terminated},
@@ -12,6 +21,10 @@ use winnow::{
 };
 
 pub fn generate(input: &str) -> Vec<(u32, u32)> {
+    try_generate(input).unwrap()
+}
+
+pub fn try_generate(input: &str) -> Option<Vec<(u32, u32)>> {
     repeat(
         0..,
         terminated(
@@ -25,7 +38,77 @@ pub fn generate(input: &str) -> Vec<(u32, u32)> {
         ),
     )
     .parse(input)
-    .unwrap()
+    .ok()
+}
+
+/// The same grammar as [`generate`], parsed by hand straight into two column vectors.
+///
+/// Nothing is allocated if the columns already have room for every row. On error the
+/// columns are left as they were and the offset of the offending row is returned.
+pub fn parse_columns(input: &[u8], left: &mut Vec<u32>, right: &mut Vec<u32>) -> Result<(), usize> {
+    fn number(input: &[u8], pos: &mut usize) -> Option<u32> {
+        match input.get(*pos)? {
+            b'0' => {
+                *pos += 1;
+                Some(0)
+            }
+            b'1'..=b'9' => {
+                let mut value = 0u32;
+                while let Some(&d @ b'0'..=b'9') = input.get(*pos) {
+                    value = value.checked_mul(10)?.checked_add((d - b'0') as u32)?;
+                    *pos += 1;
+                }
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    fn row(input: &[u8], pos: &mut usize) -> Option<(u32, u32)> {
+        let a = number(input, pos)?;
+        let spaces = input[*pos..]
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        if spaces == 0 {
+            return None;
+        }
+        *pos += spaces;
+        let b = number(input, pos)?;
+        match &input[*pos..] {
+            [b'\n', ..] => *pos += 1,
+            [b'\r', b'\n', ..] => *pos += 2,
+            _ => {}
+        }
+        Some((a, b))
+    }
+
+    let rows = left.len();
+    let mut pos = 0;
+    while pos < input.len() {
+        let start = pos;
+        let Some((a, b)) = row(input, &mut pos) else {
+            left.truncate(rows);
+            right.truncate(rows);
+            return Err(start);
+        };
+        left.push(a);
+        right.push(b);
+    }
+    Ok(())
+}
+
+/// Parse with [`parse_columns`], preallocating one slot per line.
+pub fn generate_columns_bytes(input: &str) -> Option<(Vec<u32>, Vec<u32>)> {
+    let rows = input.bytes().filter(|&b| b == b'\n').count() + 1;
+    let (mut left, mut right) = (Vec::with_capacity(rows), Vec::with_capacity(rows));
+    parse_columns(input.as_bytes(), &mut left, &mut right).ok()?;
+    Some((left, right))
+}
+
+/// [`generate`] on the byte parser, for the column variants.
+pub fn generate_bytes(input: &str) -> (Vec<u32>, Vec<u32>) {
+    generate_columns_bytes(input).unwrap()
 }
 
 pub fn part_1(input: &[(u32, u32)]) -> u32 {
@@ -50,3 +133,769 @@ pub fn part_2(input: &[(u32, u32)]) -> u32 {
         .map(|v| counts.get(&v).unwrap_or(&0) * v)
         .sum()
 }
+
+pub fn part_1_columns((l, r): &(Vec<u32>, Vec<u32>)) -> u32 {
+    let (mut l, mut r) = (l.clone(), r.clone());
+    l.sort_unstable();
+    r.sort_unstable();
+    l.into_iter().zip(r).map(|(a, b)| a.abs_diff(b)).sum()
+}
+
+pub fn part_2_columns((l, r): &(Vec<u32>, Vec<u32>)) -> u32 {
+    let mut r = r.clone();
+    r.sort_unstable();
+    let counts = r
+        .chunk_by(PartialEq::eq)
+        .map(|v| (v[0], v.len() as u32))
+        .collect::<HashMap<_, _>>();
+    l.iter().map(|v| counts.get(v).unwrap_or(&0) * v).sum()
+}
+
+/// A minimum cost one-to-one pairing of the left and right lists.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    /// (left index, right index) for every real pair; entries matched to padding are omitted.
+    pub pairs: Vec<(usize, usize)>,
+    pub cost: i64,
+}
+
+pub fn absolute_cost(l: u32, r: u32) -> Option<i64> {
+    Some((l as i64 - r as i64).abs())
+}
+
+/// The square of the distance, or None once it no longer fits in an i64 (values more than
+/// 3_037_000_499 apart), so such pairs are never matched.
+pub fn squared_cost(l: u32, r: u32) -> Option<i64> {
+    (l.abs_diff(r) as i64).checked_pow(2)
+}
+
+pub fn capped_cost(cap: i64) -> impl Fn(u32, u32) -> Option<i64> {
+    move |l, r| absolute_cost(l, r).map(|c| c.min(cap))
+}
+
+/// Wrap a cost function so that the given (left value, right value) pairs may never be matched.
+pub fn forbidding<'a>(
+    forbidden: &'a [(u32, u32)],
+    cost: impl Fn(u32, u32) -> Option<i64> + 'a,
+) -> impl Fn(u32, u32) -> Option<i64> + 'a {
+    move |l, r| {
+        if forbidden.contains(&(l, r)) {
+            None
+        } else {
+            cost(l, r)
+        }
+    }
+}
+
+/// Pair the left and right lists to minimise the total cost, via Kuhn-Munkres.
+///
+/// `cost` returns None for a pair that may not be matched. The shorter list is padded with
+/// dummy entries that match anything for free, so every entry of the shorter list is paired.
+/// Returns None if no assignment avoids the forbidden pairs, or if the total cost overflows i64.
+pub fn assign(input: &[(u32, u32)], cost: impl Fn(u32, u32) -> Option<i64>) -> Option<Assignment> {
+    let (l, r): (Vec<_>, Vec<_>) = input.iter().copied().unzip();
+    assign_lists(&l, &r, cost)
+}
+
+pub fn assign_lists(
+    l: &[u32],
+    r: &[u32],
+    cost: impl Fn(u32, u32) -> Option<i64>,
+) -> Option<Assignment> {
+    let n = l.len().max(r.len());
+    if n == 0 {
+        return Some(Assignment {
+            pairs: vec![],
+            cost: 0,
+        });
+    }
+    let costs = (0..n)
+        .flat_map(|i| (0..n).map(move |j| (i, j)))
+        .map(|(i, j)| match (l.get(i), r.get(j)) {
+            (Some(&a), Some(&b)) => cost(a, b),
+            _ => Some(0),
+        })
+        .collect::<Vec<_>>();
+    // Forbidden pairs cost more than any assignment made only of permitted pairs. The weights
+    // are widened so that neither this penalty nor the solver's sums can overflow.
+    let max = costs.iter().flatten().copied().max().unwrap_or(0) as i128;
+    let forbidden = max * n as i128 + 1;
+    let weights = Matrix::from_vec(
+        n,
+        n,
+        costs
+            .iter()
+            .map(|c| c.map_or(forbidden, i128::from))
+            .collect(),
+    )
+    .unwrap();
+    let (_, columns) = kuhn_munkres_min(&weights);
+
+    let mut pairs = vec![];
+    let mut total = 0;
+    for (i, j) in columns.into_iter().enumerate() {
+        if i >= l.len() || j >= r.len() {
+            continue;
+        }
+        total = costs[i * n + j].and_then(|c| c.checked_add(total))?;
+        pairs.push((i, j));
+    }
+    Some(Assignment { pairs, cost: total })
+}
+
+/// Read buffer each open run gets during a merge, when the budget allows it.
+const MERGE_BUFFER: usize = 8 * 1024;
+/// Runs merged at once however large the budget, so a merge never holds many files open.
+const MAX_FAN_IN: usize = 64;
+
+/// Sorted runs of one column spilled to disk; the files are removed on drop.
+#[derive(Debug, Default)]
+pub struct Runs {
+    paths: Vec<PathBuf>,
+}
+
+impl Runs {
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    fn create(&mut self, dir: &Path, capacity: usize) -> io::Result<BufWriter<File>> {
+        static RUN: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.join(format!(
+            "day1-{}-{}.run",
+            std::process::id(),
+            RUN.fetch_add(1, Ordering::Relaxed)
+        ));
+        let out = BufWriter::with_capacity(capacity, File::create(&path)?);
+        self.paths.push(path);
+        Ok(out)
+    }
+
+    fn spill(&mut self, dir: &Path, values: &mut Vec<u32>) -> io::Result<()> {
+        values.sort_unstable();
+        let mut out = self.create(dir, MERGE_BUFFER)?;
+        for v in values.drain(..) {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    /// Merge all runs into a single ascending stream, with at most [`fan_in`] files open and
+    /// about `memory_budget` bytes of buffers at any time.
+    ///
+    /// While there are more runs than that, they are first merged on disk into longer runs,
+    /// `fan_in` at a time.
+    pub fn merge(&mut self, memory_budget: usize) -> io::Result<Merge> {
+        let fan_in = fan_in(memory_budget);
+        // Intermediate passes also need a buffer for the run being written.
+        let capacity = (memory_budget / (fan_in + 1)).max(std::mem::size_of::<u32>());
+        while self.paths.len() > fan_in {
+            let mut merged = Runs::default();
+            for group in self.paths.chunks(fan_in) {
+                let dir = group[0].parent().unwrap_or(Path::new(""));
+                let mut out = merged.create(dir, capacity)?;
+                for v in Merge::open(group, capacity)? {
+                    out.write_all(&v?.to_le_bytes())?;
+                }
+                out.flush()?;
+            }
+            // Dropping the old runs removes their files.
+            *self = merged;
+        }
+        Merge::open(&self.paths, capacity)
+    }
+}
+
+/// The number of runs a merge within `memory_budget` bytes reads at once.
+pub fn fan_in(memory_budget: usize) -> usize {
+    (memory_budget / MERGE_BUFFER).clamp(2, MAX_FAN_IN)
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn read_value(reader: &mut impl Read) -> io::Result<Option<u32>> {
+    let mut buf = [0u8; 4];
+    match reader.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(u32::from_le_bytes(buf))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub struct Merge {
+    readers: Vec<BufReader<File>>,
+    heap: BinaryHeap<Reverse<(u32, usize)>>,
+}
+
+impl Merge {
+    /// k-way merge of the given runs.
+    fn open(paths: &[PathBuf], capacity: usize) -> io::Result<Merge> {
+        let mut readers = paths
+            .iter()
+            .map(|p| File::open(p).map(|f| BufReader::with_capacity(capacity, f)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut heap = BinaryHeap::with_capacity(readers.len());
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(v) = read_value(reader)? {
+                heap.push(Reverse((v, i)));
+            }
+        }
+        Ok(Merge { readers, heap })
+    }
+}
+
+impl Iterator for Merge {
+    type Item = io::Result<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((v, i)) = self.heap.pop()?;
+        match read_value(&mut self.readers[i]) {
+            Ok(Some(next)) => self.heap.push(Reverse((next, i))),
+            Ok(None) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        Some(Ok(v))
+    }
+}
+
+/// Read location pairs from `input`, spilling each column to sorted runs in `dir` whenever
+/// the buffered values would exceed `memory_budget` bytes.
+pub fn spill_runs(
+    input: impl BufRead,
+    memory_budget: usize,
+    dir: &Path,
+) -> io::Result<(Runs, Runs)> {
+    let rows = (memory_budget / (2 * std::mem::size_of::<u32>())).max(1);
+    let (mut l, mut r) = (Vec::with_capacity(rows), Vec::with_capacity(rows));
+    let (mut l_runs, mut r_runs) = (Runs::default(), Runs::default());
+    for line in input.lines() {
+        let line = line?;
+        let mut columns = line.split_ascii_whitespace().map(str::parse::<u32>);
+        match (columns.next(), columns.next(), columns.next()) {
+            (None, _, _) => continue,
+            (Some(Ok(a)), Some(Ok(b)), None) => {
+                l.push(a);
+                r.push(b);
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad location pair {line:?}"),
+                ))
+            }
+        }
+        if l.len() == rows {
+            l_runs.spill(dir, &mut l)?;
+            r_runs.spill(dir, &mut r)?;
+        }
+    }
+    if !l.is_empty() {
+        l_runs.spill(dir, &mut l)?;
+        r_runs.spill(dir, &mut r)?;
+    }
+    Ok((l_runs, r_runs))
+}
+
+/// part_1 for inputs larger than memory.
+pub fn part_1_external(input: impl BufRead, memory_budget: usize, dir: &Path) -> io::Result<u64> {
+    let (mut l, mut r) = spill_runs(input, memory_budget, dir)?;
+    // Both columns are merged side by side, so each gets half the budget.
+    l.merge(memory_budget / 2)?
+        .zip(r.merge(memory_budget / 2)?)
+        .try_fold(0, |acc, (a, b)| {
+            Ok(acc + (a? as i64 - b? as i64).unsigned_abs())
+        })
+}
+
+/// part_2 for inputs larger than memory, as a merge-join of the two sorted columns.
+pub fn part_2_external(input: impl BufRead, memory_budget: usize, dir: &Path) -> io::Result<u64> {
+    let (mut l, mut r) = spill_runs(input, memory_budget, dir)?;
+    let mut r = r.merge(memory_budget / 2)?.peekable();
+    // (value, occurrences in r) for the most recent left value, so duplicates on the left reuse it.
+    let mut current: Option<(u32, u64)> = None;
+    let mut total = 0;
+    for a in l.merge(memory_budget / 2)? {
+        let a = a?;
+        if current.map(|(v, _)| v) != Some(a) {
+            let mut count = 0;
+            while let Some(b) = r.next_if(|b| b.as_ref().map_or(true, |&b| b <= a)) {
+                if b? == a {
+                    count += 1;
+                }
+            }
+            current = Some((a, count));
+        }
+        total += a as u64 * current.unwrap().1;
+    }
+    Ok(total)
+}
+
+/// Location lists from several historians, one column per historian.
+pub fn generate_columns(input: &str) -> Vec<Vec<u32>> {
+    let rows: Vec<Vec<u32>> = repeat(
+        0..,
+        terminated::<_, Vec<u32>, _, _, _, _>(
+            separated(1.., dec_uint::<_, u32, ContextError>, space1),
+            opt(line_ending),
+        ),
+    )
+    .parse(input)
+    .unwrap();
+    let width = rows.first().map_or(0, Vec::len);
+    assert!(
+        rows.iter().all(|row| row.len() == width),
+        "every row must have {width} columns"
+    );
+    (0..width)
+        .map(|c| rows.iter().map(|row| row[c]).collect())
+        .collect()
+}
+
+/// part_1 and part_2 for every pair of columns.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnMatrix {
+    pub distance: Vec<Vec<u64>>,
+    pub similarity: Vec<Vec<u64>>,
+}
+
+fn sorted_distance(l: &[u32], r: &[u32]) -> u64 {
+    l.iter()
+        .zip(r)
+        .map(|(&a, &b)| (a as i64 - b as i64).unsigned_abs())
+        .sum()
+}
+
+/// Both columns must be sorted. The score is symmetric: each value v contributes v * count_l * count_r.
+fn sorted_similarity(l: &[u32], r: &[u32]) -> u64 {
+    let (mut l, mut r) = (
+        l.chunk_by(PartialEq::eq),
+        r.chunk_by(PartialEq::eq).peekable(),
+    );
+    let mut total = 0;
+    for run in &mut l {
+        while r.next_if(|other| other[0] < run[0]).is_some() {}
+        if let Some(other) = r.next_if(|other| other[0] == run[0]) {
+            total += run[0] as u64 * run.len() as u64 * other.len() as u64;
+        }
+    }
+    total
+}
+
+pub fn column_matrix(columns: &[Vec<u32>]) -> ColumnMatrix {
+    let sorted = columns
+        .iter()
+        .map(|c| {
+            let mut c = c.clone();
+            c.sort_unstable();
+            c
+        })
+        .collect::<Vec<_>>();
+    let pairwise = |f: fn(&[u32], &[u32]) -> u64| {
+        let mut m = vec![vec![0; sorted.len()]; sorted.len()];
+        for i in 0..sorted.len() {
+            for j in i..sorted.len() {
+                m[i][j] = f(&sorted[i], &sorted[j]);
+                m[j][i] = m[i][j];
+            }
+        }
+        m
+    };
+    ColumnMatrix {
+        distance: pairwise(sorted_distance),
+        similarity: pairwise(sorted_similarity),
+    }
+}
+
+impl ColumnMatrix {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl Display for ColumnMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, m) in [
+            ("distance", &self.distance),
+            ("similarity", &self.similarity),
+        ] {
+            let width = m
+                .iter()
+                .flatten()
+                .map(|v| v.to_string().len())
+                .max()
+                .unwrap_or(0)
+                .max(name.len());
+            write!(f, "{name:>width$}")?;
+            for j in 0..m.len() {
+                write!(f, " {:>width$}", format!("#{j}"))?;
+            }
+            writeln!(f)?;
+            for (i, row) in m.iter().enumerate() {
+                write!(f, "{:>width$}", format!("#{i}"))?;
+                for v in row {
+                    write!(f, " {v:>width$}")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const INPUT: &str = r#"3   4
+4   3
+2   5
+1   3
+3   9
+3   3"#;
+
+    #[test]
+    fn test_part_1() {
+        let input = generate(INPUT);
+        assert_eq!(part_1(&input), 11);
+    }
+
+    #[test]
+    fn test_part_2() {
+        let input = generate(INPUT);
+        assert_eq!(part_2(&input), 31);
+    }
+
+    #[test]
+    fn test_columns() {
+        let input = generate_bytes(INPUT);
+        assert_eq!(part_1_columns(&input), 11);
+        assert_eq!(part_2_columns(&input), 31);
+    }
+
+    #[test]
+    fn test_assign_absolute_matches_part_1() {
+        let input = generate(INPUT);
+        let assignment = assign(&input, absolute_cost).unwrap();
+        assert_eq!(assignment.cost, part_1(&input) as i64);
+        assert_eq!(assignment.pairs.len(), input.len());
+    }
+
+    #[test]
+    fn test_assign_squared() {
+        // sorted pairing gives (1,3),(2,3),(3,3),(3,4),(3,5),(4,9): 4+1+0+1+4+25
+        let input = generate(INPUT);
+        let assignment = assign(&input, squared_cost).unwrap();
+        assert_eq!(assignment.cost, 35);
+    }
+
+    #[test]
+    fn test_assign_squared_extremes() {
+        assert_eq!(squared_cost(0, u32::MAX), None);
+        assert_eq!(
+            squared_cost(0, 3_037_000_499),
+            Some(9_223_372_030_926_249_001)
+        );
+        assert_eq!(squared_cost(0, 3_037_000_500), None);
+        assert_eq!(assign(&[(0, u32::MAX)], squared_cost), None);
+        let assignment = assign_lists(&[0, u32::MAX], &[u32::MAX, 0], squared_cost).unwrap();
+        assert_eq!(assignment.pairs, vec![(0, 1), (1, 0)]);
+        assert_eq!(assignment.cost, 0);
+        // each square fits, but their sum does not.
+        assert_eq!(
+            assign_lists(&[0, 0], &[3_000_000_000, 3_000_000_000], squared_cost),
+            None
+        );
+    }
+
+    #[test]
+    fn test_assign_capped() {
+        // the exact matches 3-3 x3 and 4-4 are free, leaving 1 and 2 to absorb the capped 5 and 9.
+        let input = generate(INPUT);
+        assert_eq!(assign(&input, capped_cost(2)).unwrap().cost, 4);
+    }
+
+    #[test]
+    fn test_assign_forbidden() {
+        let assignment = assign(&[(1, 1), (2, 2)], forbidding(&[(1, 1)], absolute_cost)).unwrap();
+        assert_eq!(assignment.pairs, vec![(0, 1), (1, 0)]);
+        assert_eq!(assignment.cost, 2);
+        assert_eq!(
+            assign(&[(1, 1)], forbidding(&[(1, 1)], absolute_cost)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_assign_unequal_lengths() {
+        let assignment = assign_lists(&[1, 10, 20], &[11], absolute_cost).unwrap();
+        assert_eq!(assignment.pairs, vec![(1, 0)]);
+        assert_eq!(assignment.cost, 1);
+        let assignment = assign_lists(&[5], &[1, 6, 20], absolute_cost).unwrap();
+        assert_eq!(assignment.pairs, vec![(0, 1)]);
+        assert_eq!(assignment.cost, 1);
+    }
+
+    /// A deterministic pseudo-random input with plenty of repeated values.
+    fn generated_input(rows: usize) -> String {
+        let mut rng = crate::rng::Rng::new(0);
+        let mut next = || rng.below(500);
+        (0..rows)
+            .map(|_| format!("{}   {}\n", next(), next()))
+            .collect()
+    }
+
+    #[test]
+    fn test_external_spills_multiple_runs() {
+        let input = generated_input(1000);
+        let (mut l, r) = spill_runs(input.as_bytes(), 64 * 8, &std::env::temp_dir()).unwrap();
+        assert_eq!(l.len(), 16);
+        assert_eq!(r.len(), 16);
+        let merged = l
+            .merge(64 * 8)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(merged.len(), 1000);
+        assert!(merged.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_external_matches_in_memory() {
+        let dir = std::env::temp_dir();
+        for (input, budget) in [
+            (INPUT.to_string(), 16),
+            (generated_input(0), 64),
+            (generated_input(1), 8),
+            (generated_input(1000), 8),
+            (generated_input(1000), 100),
+            (generated_input(1000), 1 << 20),
+        ] {
+            let parsed = generate(&input);
+            assert_eq!(
+                part_1_external(input.as_bytes(), budget, &dir).unwrap(),
+                part_1(&parsed) as u64
+            );
+            assert_eq!(
+                part_2_external(input.as_bytes(), budget, &dir).unwrap(),
+                part_2(&parsed) as u64
+            );
+        }
+    }
+
+    #[test]
+    fn test_external_merge_passes() {
+        let input = generated_input(1000);
+        let (mut l, _) = spill_runs(input.as_bytes(), 8, &std::env::temp_dir()).unwrap();
+        assert_eq!(l.len(), 1000);
+        let merged = l.merge(4).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(l.len(), fan_in(4));
+        let mut expected = generate(&input)
+            .into_iter()
+            .map(|(a, _)| a)
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+        assert_eq!(merged, expected);
+        assert_eq!(fan_in(1 << 30), MAX_FAN_IN);
+    }
+
+    /// Rerun [`test_external_matches_in_memory`] in a child test process allowed 32 open files.
+    #[test]
+    #[cfg(unix)]
+    fn test_external_under_fd_limit() {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(r#"ulimit -n 32 && exec "$0" --exact "$1" --test-threads 1"#)
+            .arg(std::env::current_exe().unwrap())
+            .arg(
+                module_path!().split_once("::").unwrap().1.to_string()
+                    + "::test_external_matches_in_memory",
+            )
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{stdout}");
+        // a misspelt filter would pass by running nothing.
+        assert!(stdout.contains("1 passed"), "{stdout}");
+    }
+
+    #[test]
+    fn test_external_rejects_bad_rows() {
+        let err = part_1_external("1 2\n3\n".as_bytes(), 64, &std::env::temp_dir()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_column_matrix() {
+        let columns = generate_columns("3 4 3\n4 3 4\n2 5 2\n1 3 1\n3 9 3\n3 3 3\n");
+        assert_eq!(columns.len(), 3);
+        let matrix = column_matrix(&columns);
+        let input = generate(INPUT);
+        assert_eq!(matrix.distance[0][1], part_1(&input) as u64);
+        assert_eq!(matrix.similarity[0][1], part_2(&input) as u64);
+        assert_eq!(matrix.distance[1][0], matrix.distance[0][1]);
+        assert_eq!(matrix.similarity[2][1], matrix.similarity[1][2]);
+        assert_eq!(matrix.distance[0][2], 0);
+        assert_eq!(matrix.distance[2][2], 0);
+        // 1 + 2 + 3*3*3 + 4
+        assert_eq!(matrix.similarity[0][0], 34);
+    }
+
+    #[test]
+    fn test_column_matrix_output() {
+        let matrix = column_matrix(&generate_columns("1 1\n2 1\n"));
+        assert_eq!(
+            matrix.to_json(),
+            r#"{"distance":[[0,1],[1,0]],"similarity":[[3,2],[2,4]]}"#
+        );
+        assert_eq!(
+            matrix.to_string(),
+            r#"distance       #0       #1
+      #0        0        1
+      #1        1        0
+similarity         #0         #1
+        #0          3          2
+        #1          2          4
+"#
+        );
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        let (l, r) = generate_columns_bytes(INPUT).unwrap();
+        assert_eq!(l, vec![3, 4, 2, 1, 3, 3]);
+        assert_eq!(r, vec![4, 3, 5, 3, 9, 3]);
+        // a zero cannot be followed by more digits, so the second row starts straight after it.
+        assert_eq!(
+            generate_columns_bytes("1 00 5").unwrap(),
+            (vec![1, 0], vec![0, 5])
+        );
+        let (mut l, mut r) = (vec![7], vec![8]);
+        assert_eq!(
+            parse_columns(b"1 2\r\n3 4294967296\n", &mut l, &mut r),
+            Err(5)
+        );
+        assert_eq!((l, r), (vec![7], vec![8]));
+    }
+
+    fn arb_valid_input() -> impl Strategy<Value = String> {
+        let number = prop_oneof![0u32..10, any::<u32>()];
+        let row = (
+            number.clone(),
+            "[ \t]{1,3}",
+            number,
+            prop_oneof!["", "\n", "\r\n"],
+        );
+        prop::collection::vec(row, 0..20).prop_map(|rows| {
+            rows.into_iter()
+                .map(|(a, space, b, end)| format!("{a}{space}{b}{end}"))
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_columns_valid(input in arb_valid_input()) {
+            let expected = try_generate(&input).map(|v| v.into_iter().unzip());
+            prop_assert_eq!(generate_columns_bytes(&input), expected);
+        }
+
+        #[test]
+        fn test_parse_columns_arbitrary(input in "[0-9 \t\r\nx]{0,40}") {
+            let expected = try_generate(&input).map(|v| v.into_iter().unzip());
+            prop_assert_eq!(generate_columns_bytes(&input), expected);
+        }
+
+        #[test]
+        fn test_parse_columns_near_u32_max(a in 4_294_967_000u64..4_294_968_000, b in any::<u32>()) {
+            let input = format!("{a}  {b}\n");
+            let expected = try_generate(&input).map(|v| v.into_iter().unzip());
+            prop_assert_eq!(generate_columns_bytes(&input), expected);
+        }
+    }
+
+    /// part_1 without sorting: pair each value by its rank within its column.
+    fn part_1_oracle(input: &[(u32, u32)]) -> u32 {
+        let rank = |column: &dyn Fn(usize) -> u32, i: usize| {
+            (0..input.len())
+                .filter(|&j| column(j) < column(i) || (column(j) == column(i) && j < i))
+                .count()
+        };
+        let left = |i: usize| input[i].0;
+        let right = |i: usize| input[i].1;
+        (0..input.len())
+            .map(|i| {
+                let k = rank(&left, i);
+                let j = (0..input.len()).find(|&j| rank(&right, j) == k).unwrap();
+                left(i).abs_diff(right(j))
+            })
+            .sum()
+    }
+
+    fn part_2_oracle(input: &[(u32, u32)]) -> u32 {
+        input
+            .iter()
+            .map(|&(l, _)| l * input.iter().filter(|&&(_, r)| r == l).count() as u32)
+            .sum()
+    }
+
+    fn arb_pairs() -> impl Strategy<Value = Vec<(u32, u32)>> {
+        // a narrow value range some of the time, so part_2 sees repeated values.
+        let value = prop_oneof![0u32..10, 0u32..100_000];
+        prop::collection::vec((value.clone(), value), 0..40)
+    }
+
+    proptest! {
+        #[test]
+        fn test_part_1_oracle(input in arb_pairs()) {
+            prop_assert_eq!(part_1(&input), part_1_oracle(&input));
+        }
+
+        #[test]
+        fn test_part_2_oracle(input in arb_pairs()) {
+            prop_assert_eq!(part_2(&input), part_2_oracle(&input));
+        }
+
+        #[test]
+        fn test_columns_match_pairs(input in arb_pairs()) {
+            let columns = input.iter().copied().unzip();
+            prop_assert_eq!(part_1_columns(&columns), part_1(&input));
+            prop_assert_eq!(part_2_columns(&columns), part_2(&input));
+        }
+
+        #[test]
+        fn test_row_permutation(
+            (input, shuffled) in arb_pairs().prop_flat_map(|v| (Just(v.clone()), Just(v).prop_shuffle()))
+        ) {
+            prop_assert_eq!(part_1(&input), part_1(&shuffled));
+            prop_assert_eq!(part_2(&input), part_2(&shuffled));
+        }
+
+        #[test]
+        fn test_swap_columns(input in arb_pairs()) {
+            let swapped = input.iter().map(|&(l, r)| (r, l)).collect::<Vec<_>>();
+            prop_assert_eq!(part_1(&input), part_1(&swapped));
+        }
+
+        #[test]
+        fn test_duplicate_right(input in arb_pairs()) {
+            // pad the left with zeros, which never add to the similarity score.
+            let doubled = input
+                .iter()
+                .copied()
+                .chain(input.iter().map(|&(_, r)| (0, r)))
+                .collect::<Vec<_>>();
+            prop_assert_eq!(part_2(&doubled), 2 * part_2(&input));
+        }
+    }
+}
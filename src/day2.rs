@@ -1,4 +1,12 @@
-use winnow::combinator::{opt, terminated};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::ops::{Range, RangeInclusive};
+
+use crate::rng::Rng;
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator as _};
+
+use winnow::combinator::{alt, opt, terminated};
 use winnow::{ascii::space0, combinator::repeat};
 use winnow::{
     ascii::{dec_int, line_ending},
@@ -6,11 +14,26 @@ use winnow::{
     prelude::*,
 };
 
+/// The reports, which must have every level recorded.
 pub fn generate(input: &str) -> Vec<Vec<i32>> {
+    generate_partial(input)
+        .into_iter()
+        .map(|report| {
+            report
+                .into_iter()
+                .collect::<Option<_>>()
+                .expect("unknown level")
+        })
+        .collect()
+}
+
+/// The reports, where `?` stands for a level that wasn't recorded.
+pub fn generate_partial(input: &str) -> Vec<Vec<Option<i32>>> {
+    let level = alt((dec_int::<_, i32, ContextError>.map(Some), "?".map(|_| None)));
     repeat(
         0..,
-        terminated::<_, Vec<i32>, _, _, _, _>(
-            repeat(1.., terminated(dec_int::<_, i32, ContextError>, space0)),
+        terminated::<_, Vec<Option<i32>>, _, _, _, _>(
+            repeat(1.., terminated(level, space0)),
             opt(line_ending),
         ),
     )
@@ -20,143 +43,1385 @@ pub fn generate(input: &str) -> Vec<Vec<i32>> {
 
 pub fn part_1(input: &[Vec<i32>]) -> usize {
     // count the reports which are monotonic in any direction with step sizes between 1 and 3
+    part_1_with(input, &SafetyPolicy::default())
+}
+
+pub fn part_1_with(input: &[Vec<i32>], policy: &SafetyPolicy) -> usize {
+    input.iter().filter(|report| policy.is_safe(report)).count()
+}
+
+/// The rules a report has to follow to be safe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SafetyPolicy {
+    /// Smallest and largest size of a step between adjacent levels.
+    pub min_step: i32,
+    pub max_step: i32,
+    pub increasing: bool,
+    pub decreasing: bool,
+    /// Tolerate adjacent equal levels, whatever `min_step` is.
+    pub allow_equal: bool,
+    /// Every step of a report has to go in the same direction as the first.
+    pub lock_direction: bool,
+}
+
+impl SafetyPolicy {
+    /// The puzzle rules: strictly monotonic with steps of 1 to 3.
+    pub const PUZZLE: SafetyPolicy = SafetyPolicy {
+        min_step: 1,
+        max_step: 3,
+        increasing: true,
+        decreasing: true,
+        allow_equal: false,
+        lock_direction: true,
+    };
+
+    pub fn is_safe(&self, report: &[i32]) -> bool {
+        report
+            .windows(2)
+            .try_fold(0, |dir, pair| self.fold_pair(pair[0], pair[1], dir))
+            .is_ok()
+    }
+
+    /// Check one step given the direction bits seen so far (1 increasing, 2 decreasing).
+    fn fold_pair(&self, l: i32, r: i32, dir: i32) -> Result<i32, LevelError> {
+        // in i64, as levels at opposite ends of the i32 range are further apart than i32::MAX.
+        let step = r as i64 - l as i64;
+        if step == 0 && self.allow_equal {
+            return Ok(dir);
+        }
+        if !(self.min_step as i64..=self.max_step as i64).contains(&step.abs()) {
+            return Err(LevelError::Size(step.unsigned_abs() as usize));
+        }
+        let direction = match step {
+            ..=-1 if self.decreasing => 2,
+            1.. if self.increasing => 1,
+            _ => return Err(LevelError::Direction),
+        };
+        if !self.lock_direction {
+            return Ok(dir);
+        }
+        match direction | dir {
+            dir @ 1 | dir @ 2 => Ok(dir),
+            _ => Err(LevelError::Direction),
+        }
+    }
+}
+
+impl Default for SafetyPolicy {
+    fn default() -> Self {
+        Self::PUZZLE
+    }
+}
+
+fn fold_pair(l: i32, r: i32, dir: i32) -> Result<i32, LevelError> {
+    SafetyPolicy::PUZZLE.fold_pair(l, r, dir)
+}
+
+/// As part 1, but tolerate a single bad level: retry the report with each level removed in turn.
+pub fn part_2(input: &[Vec<i32>]) -> usize {
+    part_2_with(input, &SafetyPolicy::default())
+}
+
+pub fn part_2_with(input: &[Vec<i32>], policy: &SafetyPolicy) -> usize {
     input
         .iter()
-        .filter(|report| {
-            report
-                .windows(2)
-                .try_fold(0, |dir, pair| fold_pair(pair[0], pair[1], dir))
-                .is_ok()
+        .filter(|&report| {
+            (0..=report.len()).any(|i| {
+                let mut report = report.clone();
+                if i > 0 {
+                    report.remove(i - 1);
+                }
+                policy.is_safe(&report)
+            })
         })
         .count()
 }
 
-fn fold_pair(l: i32, r: i32, dir: i32) -> Result<i32, ()> {
-    let direction = match r - l {
-        ..=-4 | 4.. => return Err(()),
-        ..=-1 => 2 | dir,
-        1.. => 1 | dir,
-        _ => return Err(()),
-    };
-    match direction | dir {
-        dir @ 1 | dir @ 2 => Ok(dir),
-        _ => Err(()),
-    }
+/// As part 2, but deciding each report in a single pass without copying it.
+pub fn part_2_single_pass(input: &[Vec<i32>]) -> usize {
+    input.iter().filter(|report| dampened_safe(report)).count()
 }
 
-/// NOTETO SELF:
-/// if the step is too large,  (1,7,8) recovery means taking the prior value and checking the step again
-///  - we detect large steps on the second element
-/// if the sign was wrong, (3,2,3) recovery means taking the next value
-///  - we detect the sign error on the first element
-pub fn part_2(input: &[Vec<i32>]) -> usize {
-    // as part 1, but tolerate a single bad step
-    if true {
-        input
+fn dampened_safe(report: &[i32]) -> bool {
+    let mut classifier = ReportClassifier::default();
+    report.iter().for_each(|&level| classifier.push(level));
+    classifier.classify() != Classification::Unsafe
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classification {
+    Safe,
+    /// Safe once the Problem Dampener removes a level.
+    DampenerSafe,
+    Unsafe,
+}
+
+/// Classifies a report one level at a time, in constant memory.
+///
+/// For each direction, track whether the levels up to the current one are safe when kept in
+/// full (clean) or with one earlier level removed (dampened), and the same for the level
+/// before, so that a bad level can be skipped by stepping straight over it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ReportClassifier {
+    levels: usize,
+    /// The two most recent levels, oldest first; None for a far level.
+    last: [Option<i32>; 2],
+    /// (clean, dampened) for the previous and current levels; increasing then decreasing.
+    states: [[(bool, bool); 2]; 2],
+}
+
+impl ReportClassifier {
+    pub fn push(&mut self, level: i32) {
+        self.push_level(Some(level));
+    }
+
+    /// As [`Self::push`], where None is a level too far from the levels around it to be kept
+    /// next to any of them.
+    fn push_level(&mut self, level: Option<i32>) {
+        // direction bits as used by fold_pair: 1 increasing, 2 decreasing.
+        let ok = |dir: i32, l: Option<i32>, r: Option<i32>| {
+            l.zip(r).is_some_and(|(l, r)| fold_pair(l, r, dir).is_ok())
+        };
+        for (dir, [prev, current]) in [1, 2].into_iter().zip(self.states.iter_mut()) {
+            match self.levels {
+                0 => {}
+                // dropping the first level always leaves a dampened but safe single level.
+                1 => *current = (ok(dir, self.last[1], level), true),
+                _ => {
+                    let step = ok(dir, self.last[1], level);
+                    let skip = ok(dir, self.last[0], level);
+                    let next = (current.0 && step, (current.1 && step) || (prev.0 && skip));
+                    *prev = *current;
+                    *current = next;
+                }
+            }
+            if self.levels == 1 {
+                *prev = (true, true);
+            }
+        }
+        self.last = [self.last[1], level];
+        self.levels += 1;
+    }
+
+    pub fn classify(&self) -> Classification {
+        if self.levels <= 1 || self.states.iter().any(|[_, current]| current.0) {
+            Classification::Safe
+        } else if self
+            .states
             .iter()
-            .filter(|&report| {
-                (0..=report.len())
-                    .filter(|&i| {
-                        let mut report = report.clone();
-                        if i > 0 {
-                            report.remove(i - 1);
+            // either the last level is kept, or it is dropped after an otherwise clean report.
+            .any(|[prev, current]| current.1 || prev.0)
+        {
+            Classification::DampenerSafe
+        } else {
+            Classification::Unsafe
+        }
+    }
+}
+
+/// Classify each report read from `input` without holding on to the input: levels are parsed
+/// straight out of the reader's buffer, so memory use doesn't depend on line length either.
+pub fn classify_stream<R: BufRead>(input: R) -> ReportStream<R> {
+    ReportStream { input, done: false }
+}
+
+/// The part_1 and part_2 counts of the reports read from `input`.
+pub fn count_stream(input: impl BufRead) -> io::Result<(usize, usize)> {
+    classify_stream(input).try_fold((0, 0), |(safe, dampened), classification| {
+        let classification = classification?;
+        Ok((
+            safe + (classification == Classification::Safe) as usize,
+            dampened + (classification != Classification::Unsafe) as usize,
+        ))
+    })
+}
+
+pub struct ReportStream<R> {
+    input: R,
+    done: bool,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn flush_level(
+    level: &mut Option<(i64, Option<i64>)>,
+    report: &mut ReportClassifier,
+) -> io::Result<()> {
+    match level.take() {
+        None => {}
+        Some((sign, Some(value))) => {
+            report.push(i32::try_from(sign * value).map_err(|_| invalid("level out of range"))?)
+        }
+        Some((_, None)) => return Err(invalid("sign without a level")),
+    }
+    Ok(())
+}
+
+impl<R: BufRead> ReportStream<R> {
+    fn next_report(&mut self) -> io::Result<Option<Classification>> {
+        let mut report = ReportClassifier::default();
+        // the sign and digits of the level being read.
+        let mut level = None;
+        loop {
+            let buf = self.input.fill_buf()?;
+            if buf.is_empty() {
+                flush_level(&mut level, &mut report)?;
+                return Ok((report.levels > 0).then(|| report.classify()));
+            }
+            let mut used = 0;
+            let mut end_of_report = false;
+            for &b in buf {
+                used += 1;
+                match b {
+                    b'0'..=b'9' => {
+                        let (_, value) = level.get_or_insert((1, None));
+                        let digit = (b - b'0') as i64;
+                        *value = Some(value.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                    }
+                    b'-' | b'+' => {
+                        flush_level(&mut level, &mut report)?;
+                        level = Some((if b == b'-' { -1 } else { 1 }, None));
+                    }
+                    b' ' | b'\t' | b'\r' => flush_level(&mut level, &mut report)?,
+                    b'\n' => {
+                        flush_level(&mut level, &mut report)?;
+                        if report.levels > 0 {
+                            end_of_report = true;
+                            break;
                         }
-                        let succeeds = report
-                            .windows(2)
-                            .try_fold(0, |dir, pair| fold_pair(pair[0], pair[1], dir))
-                            .is_ok();
-                        // eprintln!("{:?} -> {}", report, succeeds);
-                        succeeds
-                    })
-                    .take(1)
-                    .count()
-                    > 0
+                    }
+                    _ => return Err(invalid("unexpected byte in report")),
+                }
+            }
+            self.input.consume(used);
+            if end_of_report {
+                return Ok(Some(report.classify()));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ReportStream<R> {
+    type Item = io::Result<Classification>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_report().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+pub fn part_1_rayon(input: &[Vec<i32>]) -> usize {
+    let policy = SafetyPolicy::default();
+    input
+        .par_iter()
+        .filter(|report| policy.is_safe(report))
+        .count()
+}
+
+pub fn part_2_rayon(input: &[Vec<i32>]) -> usize {
+    input
+        .par_iter()
+        .filter(|report| dampened_safe(report))
+        .count()
+}
+
+pub fn part_1_flat(input: &[Vec<i32>]) -> usize {
+    FlatReports::from(input).count_safe()
+}
+
+pub fn part_2_flat(input: &[Vec<i32>]) -> usize {
+    FlatReports::from(input).count_dampened()
+}
+
+/// Every report's levels in one contiguous buffer, so that the steps of all reports are
+/// computed together in simple loops the compiler can vectorise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatReports {
+    levels: Vec<i32>,
+    /// Where each report starts in `levels`, followed by the end of the last report.
+    offsets: Vec<usize>,
+}
+
+impl From<&[Vec<i32>]> for FlatReports {
+    fn from(input: &[Vec<i32>]) -> Self {
+        let mut levels = Vec::with_capacity(input.iter().map(Vec::len).sum());
+        let mut offsets = Vec::with_capacity(input.len() + 1);
+        offsets.push(0);
+        for report in input {
+            levels.extend_from_slice(report);
+            offsets.push(levels.len());
+        }
+        FlatReports { levels, offsets }
+    }
+}
+
+impl FlatReports {
+    pub fn reports(&self) -> impl Iterator<Item = &[i32]> {
+        self.offsets.windows(2).map(|w| &self.levels[w[0]..w[1]])
+    }
+
+    /// For each level, whether the step to the level `distance` further on is a safe increase
+    /// (bit 1) or a safe decrease (bit 2). Steps that cross into the next report are ignored.
+    fn step_flags(&self, distance: usize) -> Vec<u8> {
+        let levels = &self.levels;
+        levels
+            .iter()
+            .zip(&levels[distance.min(levels.len())..])
+            .map(|(&l, &r)| {
+                // in i64, as the step across a report boundary may be anything.
+                let step = r as i64 - l as i64;
+                ((1..=3).contains(&step) as u8) | (((-3..=-1).contains(&step) as u8) << 1)
+            })
+            .collect()
+    }
+
+    pub fn count_safe(&self) -> usize {
+        let steps = self.step_flags(1);
+        self.offsets
+            .windows(2)
+            .filter(|w| {
+                w[1] - w[0] <= 1 || steps[w[0]..w[1] - 1].iter().fold(3, |a, &f| a & f) != 0
             })
             .count()
-    } else {
-        // single pass version WIP
-        input
-            .iter()
-            .filter(|&report| {
-                fn validate_report(report: &Vec<i32>, error_location: Option<usize>) -> bool {
-                    dbg!(report);
-                    report
-                        .windows(2)
-                        .enumerate()
-                        .try_fold(
-                            (0, None, error_location),
-                            |(direction, alternate_left, error_location), (i, pair)| {
-                                dbg!((direction, alternate_left, error_location, pair));
-                                let (new_error_location, folded) = match error_location {
-                                    // If an error has occured on the previous step, (e.g. 1->7 from 1 7 8) then
-                                    // going from previous to next is the only valid attempt
-                                    Some(loc) if loc + 1 == i => (
-                                        error_location,
-                                        fold_pair(
-                                            alternate_left.unwrap_or(pair[0]),
-                                            pair[1],
-                                            direction,
-                                        ),
-                                    ),
-                                    // If an error has occured earlier, then we can only try the current pair
-                                    Some(_) => {
-                                        (error_location, fold_pair(pair[0], pair[1], direction))
-                                    }
-                                    // Try this pair two ways: once as given, and only if that fails, once using the previous element as the left (if it exists).
-                                    _ => {
-                                        let current = fold_pair(pair[0], pair[1], direction);
-
-                                        if current.is_ok() {
-                                            (None, current)
-                                        } else {
-                                            // 1 2 3 -> [01, 12] : last interval is 1 when length is three
-                                            // intervals, not indexes
-                                            dbg!((i, report.len()));
-                                            if i + 2 == report.len() {
-                                                // no current error at the end, just skip the last element.
-                                                (None, Ok(direction))
-                                            } else if alternate_left.is_some() {
-                                                let alternate = fold_pair(
-                                                    alternate_left.unwrap_or(pair[0]),
-                                                    pair[1],
-                                                    direction,
-                                                );
-                                                (Some(i), alternate)
-                                            } else {
-                                                // error on the first step
-                                                (Some(i), current)
-                                            }
-                                        }
-                                    } // fold_pair(pair[0], pair[1], direction).or_else(|_| {
-                                      //     fold_pair(alternate_left.unwrap_or(pair[0]), pair[1], direction)
-                                      // }),
-                                };
-                                // let folded = fold_pair(pair[0], pair[1], direction).or_else(|_| {
-                                //     fold_pair(alternate_left.unwrap_or(pair[0]), pair[1], direction)
-                                // });
-                                if let Ok(new_direction) = folded {
-                                    return Ok((new_direction, Some(pair[0]), new_error_location));
-                                } else if error_location.is_some() {
-                                    // second error
-                                    return Err(());
-                                } else {
-                                    // record the error, and try again with the next pair.
-                                    return Ok((direction, Some(pair[0]), new_error_location));
-                                }
-                            },
-                        )
-                        .is_ok()
-                }
+    }
 
-                // edge case: cannot infer fault location correctly if the first element is the faulty one; try skipping it entirely and re-run.
-                validate_report(report, None) || {
-                    validate_report(&report.iter().copied().skip(1).collect(), Some(0))
+    /// As [`ReportClassifier`], with the directions held as bits so both are tracked at once.
+    pub fn count_dampened(&self) -> usize {
+        let steps = self.step_flags(1);
+        let skips = self.step_flags(2);
+        self.offsets
+            .windows(2)
+            .filter(|w| {
+                let (start, end) = (w[0], w[1]);
+                if end - start <= 2 {
+                    return true;
+                }
+                let (mut prev, mut current) = ((3, 3), (steps[start], 3));
+                for i in start + 2..end {
+                    let next = (
+                        current.0 & steps[i - 1],
+                        (current.1 & steps[i - 1]) | (prev.0 & skips[i - 2]),
+                    );
+                    prev = current;
+                    current = next;
                 }
+                current.1 | prev.0 != 0
             })
             .count()
     }
 }
 
+/// part_1 as the k = 0 case of [`dampen`].
+pub fn part_1_dp(input: &[Vec<i32>]) -> usize {
+    count_dampened(input, 0)
+}
+
+/// part_2 as the k = 1 case of [`dampen`].
+pub fn part_2_dp(input: &[Vec<i32>]) -> usize {
+    count_dampened(input, 1)
+}
+
+pub fn count_dampened(input: &[Vec<i32>], k: usize) -> usize {
+    count_dampened_with(input, k, &SafetyPolicy::default())
+}
+
+pub fn count_dampened_with(input: &[Vec<i32>], k: usize, policy: &SafetyPolicy) -> usize {
+    input
+        .iter()
+        .filter(|report| dampen_with(report, k, policy).is_some())
+        .count()
+}
+
+/// The smallest set of level indices, at most `k` of them, whose removal makes the report safe.
+///
+/// For each direction, `removed[j]` is the fewest removals for a safe prefix ending with level
+/// j kept. The previous kept level is at most k + 1 levels back, so this is O(n * k).
+pub fn dampen(report: &[i32], k: usize) -> Option<Vec<usize>> {
+    dampen_with(report, k, &SafetyPolicy::default())
+}
+
+pub fn dampen_with(report: &[i32], k: usize, policy: &SafetyPolicy) -> Option<Vec<usize>> {
+    let n = report.len();
+    if n <= 1 {
+        return Some(vec![]);
+    }
+    [1, 2]
+        .into_iter()
+        .filter_map(|dir| {
+            let mut removed = vec![usize::MAX; n];
+            let mut previous = vec![None; n];
+            for j in 0..n {
+                removed[j] = j;
+                for i in j.saturating_sub(k + 1)..j {
+                    if removed[i] == usize::MAX
+                        || policy.fold_pair(report[i], report[j], dir).is_err()
+                    {
+                        continue;
+                    }
+                    let cost = removed[i] + (j - i - 1);
+                    if cost < removed[j] {
+                        removed[j] = cost;
+                        previous[j] = Some(i);
+                    }
+                }
+                if removed[j] > k {
+                    removed[j] = usize::MAX;
+                }
+            }
+            let (cost, last) = (0..n)
+                .filter(|&j| removed[j] != usize::MAX)
+                .map(|j| (removed[j] + (n - 1 - j), j))
+                .min()?;
+            if cost > k {
+                return None;
+            }
+            let mut kept = vec![false; n];
+            let mut j = Some(last);
+            while let Some(i) = j {
+                kept[i] = true;
+                j = previous[i];
+            }
+            Some((0..n).filter(|&i| !kept[i]).collect::<Vec<_>>())
+        })
+        .min_by_key(Vec::len)
+}
+
+/// Why a step between two adjacent levels is unsafe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelError {
+    /// The step goes against the report's direction, or in a direction the policy forbids.
+    Direction,
+    /// The step is this size, outside the permitted range.
+    Size(usize),
+}
+
+/// A bad step, between levels `interval` and `interval + 1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadLevel {
+    pub interval: usize,
+    pub kind: LevelError,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Safety {
+    Safe,
+    /// Safe once the level at this index is removed.
+    Dampened(usize),
+    Unsafe,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub safety: Safety,
+    /// The first bad step of the report as given, if any.
+    pub first_error: Option<BadLevel>,
+}
+
+/// Diagnose every report under the puzzle rules.
+pub fn diagnose(input: &[Vec<i32>]) -> Vec<Diagnosis> {
+    let policy = SafetyPolicy::default();
+    input
+        .iter()
+        .map(|report| diagnose_report(report, &policy))
+        .collect()
+}
+
+pub fn diagnose_report(report: &[i32], policy: &SafetyPolicy) -> Diagnosis {
+    let first_error = report
+        .windows(2)
+        .enumerate()
+        .try_fold(0, |dir, (interval, pair)| {
+            policy
+                .fold_pair(pair[0], pair[1], dir)
+                .map_err(|kind| BadLevel { interval, kind })
+        })
+        .err();
+    let safety = match (&first_error, dampen_with(report, 1, policy).as_deref()) {
+        (None, _) => Safety::Safe,
+        (Some(_), Some(&[level])) => Safety::Dampened(level),
+        _ => Safety::Unsafe,
+    };
+    Diagnosis {
+        safety,
+        first_error,
+    }
+}
+
+/// A report built with known faults, and the classification those faults imply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedReport {
+    pub levels: Vec<i32>,
+    pub faults: Vec<BadLevel>,
+    /// 1 for increasing, -1 for decreasing.
+    pub direction: i32,
+    pub classification: Classification,
+}
+
+/// Many generated reports in puzzle input format, with the expected answers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedInput {
+    pub text: String,
+    pub part_1: usize,
+    pub part_2: usize,
+}
+
+/// A seeded generator of reports with injected faults.
+///
+/// Reports step by 2 in one direction, and each fault rewrites a single interval while keeping
+/// every other step. One fault is always recoverable: a zero or reversed step merges with a
+/// neighbouring step of 2 into a valid one, and an oversized step is only placed at either end
+/// where its outer level can be dropped. Several faults are placed at least two intervals apart,
+/// so no single removal reaches two of them.
+pub struct ReportGenerator {
+    rng: Rng,
+}
+
+impl ReportGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// The shortest report that can carry `faults` faults and keep its classification.
+    pub fn min_len(faults: usize) -> usize {
+        match faults {
+            0 => 0,
+            // one good step, so that part 1 sees the fault.
+            1 => 3,
+            // spaced faults, and two good steps so the opposite direction can't be dampened either.
+            _ => (2 * faults).max(faults + 3),
+        }
+    }
+
+    pub fn report(&mut self, len: usize, faults: &[LevelError]) -> GeneratedReport {
+        assert!(
+            len >= Self::min_len(faults.len()),
+            "{} faults need at least {} levels",
+            faults.len(),
+            Self::min_len(faults.len())
+        );
+        assert!(
+            faults.iter().all(|f| !matches!(f, LevelError::Size(1..=3))),
+            "steps of 1 to 3 are not faults"
+        );
+        let direction = if self.rng.next_u64() & 1 == 0 { 1 } else { -1 };
+        let start = self.rng.below(100) as i32;
+        let mut levels = (0..len as i32)
+            .map(|i| start + 2 * direction * i)
+            .collect::<Vec<_>>();
+
+        let intervals = len.saturating_sub(1);
+        let faults = match faults {
+            [] => vec![],
+            [kind @ LevelError::Size(1..)] => {
+                let interval = if self.rng.next_u64() & 1 == 0 {
+                    0
+                } else {
+                    intervals - 1
+                };
+                vec![BadLevel {
+                    interval,
+                    kind: kind.clone(),
+                }]
+            }
+            _ => {
+                let slack = intervals + 1 - 2 * faults.len();
+                let mut extra = faults
+                    .iter()
+                    .map(|_| self.rng.below(slack + 1))
+                    .collect::<Vec<_>>();
+                extra.sort_unstable();
+                faults
+                    .iter()
+                    .zip(extra)
+                    .enumerate()
+                    .map(|(i, (kind, extra))| BadLevel {
+                        interval: 2 * i + extra,
+                        kind: kind.clone(),
+                    })
+                    .collect()
+            }
+        };
+        for bad in &faults {
+            apply_error(&mut levels, bad, direction);
+        }
+        if let Some(&min) = levels.iter().min() {
+            levels.iter_mut().for_each(|v| *v -= min.min(0));
+        }
+
+        let classification = match faults.len() {
+            0 => Classification::Safe,
+            1 => Classification::DampenerSafe,
+            _ => Classification::Unsafe,
+        };
+        GeneratedReport {
+            levels,
+            faults,
+            direction,
+            classification,
+        }
+    }
+
+    /// A report of 5 to `max_len` levels with up to two faults of random kinds.
+    pub fn random_report(&mut self, max_len: usize) -> GeneratedReport {
+        let len = 5 + self.rng.below(max_len.max(5) - 4);
+        let faults = (0..self.rng.below(3))
+            .map(|_| match self.rng.below(3) {
+                0 => LevelError::Direction,
+                1 => LevelError::Size(0),
+                _ => LevelError::Size(4 + self.rng.below(6)),
+            })
+            .collect::<Vec<_>>();
+        self.report(len, &faults)
+    }
+
+    /// `reports` random reports as puzzle input, e.g. to benchmark against large inputs.
+    pub fn input(&mut self, reports: usize, max_len: usize) -> GeneratedInput {
+        let mut generated = GeneratedInput {
+            text: String::new(),
+            part_1: 0,
+            part_2: 0,
+        };
+        for _ in 0..reports {
+            let report = self.random_report(max_len);
+            let line = report.levels.iter().map(i32::to_string).collect::<Vec<_>>();
+            generated.text.push_str(&line.join(" "));
+            generated.text.push('\n');
+            generated.part_1 += (report.classification == Classification::Safe) as usize;
+            generated.part_2 += (report.classification != Classification::Unsafe) as usize;
+        }
+        generated
+    }
+}
+
+/// Rewrite one interval of the report to the given fault, shifting the levels after it so
+/// every other step is unchanged.
+pub fn apply_error(v: &mut [i32], bad: &BadLevel, direction: i32) {
+    assert_ne!(direction, 0);
+    let existing_step = v[bad.interval + 1] - v[bad.interval];
+    let delta = match bad.kind {
+        LevelError::Direction => -existing_step - direction,
+        // calulate the change needed to make the interval the given size
+        LevelError::Size(size) => (direction * size as i32) - existing_step,
+    };
+    for level in &mut v[bad.interval + 1..] {
+        *level += delta;
+    }
+}
+
+/// What a repair may do to a level, and what it costs. None forbids that kind of change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepairCosts {
+    pub edit: Option<usize>,
+    pub delete: Option<usize>,
+}
+
+impl RepairCosts {
+    /// Only change level values, counting each changed level.
+    pub const EDITS: RepairCosts = RepairCosts {
+        edit: Some(1),
+        delete: None,
+    };
+}
+
+/// A cheapest set of changes that makes a report safe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repair {
+    /// The corrected report, without the deleted levels.
+    pub levels: Vec<i32>,
+    /// Indices into the original report.
+    pub edited: Vec<usize>,
+    pub deleted: Vec<usize>,
+    pub cost: usize,
+}
+
+/// The fewest level values to change to make the report safe, and the corrected report.
+pub fn repair(report: &[i32], policy: &SafetyPolicy) -> Option<Repair> {
+    repair_with(report, policy, &RepairCosts::EDITS)
+}
+
+/// The cheapest mix of edits and deletions that makes the report safe.
+///
+/// Some levels are kept unchanged as anchors. Between two anchors the levels are edited or
+/// deleted, which works if the edited levels can bridge the gap between the anchors in
+/// permitted steps; levels outside the first and last anchor can always be edited to extend
+/// the report, or deleted.
+pub fn repair_with(report: &[i32], policy: &SafetyPolicy, costs: &RepairCosts) -> Option<Repair> {
+    let modes: &[i32] = if policy.lock_direction { &[1, 2] } else { &[0] };
+    modes
+        .iter()
+        .filter_map(|&dir| repair_in(report, costs, &Steps::new(policy, dir)))
+        .min_by_key(|repair| repair.cost)
+}
+
+/// The steps a policy permits, in one direction mode: 1 increasing, 2 decreasing, 0 either.
+struct Steps {
+    up: Option<(i64, i64)>,
+    down: Option<(i64, i64)>,
+    equal: bool,
+}
+
+impl Steps {
+    fn new(policy: &SafetyPolicy, dir: i32) -> Self {
+        let range = Some((policy.min_step.max(1) as i64, policy.max_step as i64))
+            .filter(|(lo, hi)| lo <= hi);
+        Steps {
+            up: range.filter(|_| policy.increasing && dir != 2),
+            down: range.filter(|_| policy.decreasing && dir != 1),
+            equal: policy.allow_equal,
+        }
+    }
+
+    /// Every permitted step, or None if there are more than `limit` of them.
+    fn all(&self, limit: usize) -> Option<Vec<i32>> {
+        let width = |range: Option<(i64, i64)>| range.map_or(0, |(lo, hi)| (hi - lo + 1) as usize);
+        if width(self.up) + width(self.down) > limit {
+            return None;
+        }
+        let up = self.up.into_iter().flat_map(|(lo, hi)| lo..=hi);
+        let down = self.down.into_iter().flat_map(|(lo, hi)| -hi..=-lo);
+        let equal = self.equal.then_some(0);
+        Some(up.chain(down).chain(equal).map(|s| s as i32).collect())
+    }
+
+    fn contains(&self, step: i32) -> bool {
+        let step = step as i64;
+        let within = |range: Option<(i64, i64)>, step: i64| {
+            range.is_some_and(|(lo, hi)| (lo..=hi).contains(&step))
+        };
+        within(self.up, step) || within(self.down, -step) || (self.equal && step == 0)
+    }
+
+    fn single(&self) -> Option<i32> {
+        self.up
+            .map(|(lo, _)| lo as i32)
+            .or(self.down.map(|(lo, _)| -lo as i32))
+            .or(self.equal.then_some(0))
+    }
+
+    /// `count` permitted steps that add up to `total`, if there are any.
+    fn fill(&self, total: i32, count: usize) -> Option<Vec<i32>> {
+        let total = total as i64;
+        let (up, down) = (self.up.unwrap_or((0, 0)), self.down.unwrap_or((0, 0)));
+        let ups = if self.up.is_some() { count } else { 0 };
+        for u in 0..=ups {
+            let downs = if self.down.is_some() { count - u } else { 0 };
+            for d in 0..=downs {
+                if u + d < count && !self.equal {
+                    continue;
+                }
+                let (u_, d_) = (u as i64, d as i64);
+                let low = u_ * up.0 - d_ * down.1;
+                if !(low..=u_ * up.1 - d_ * down.0).contains(&total) {
+                    continue;
+                }
+                // start from the lowest sum, and raise each step as far as needed.
+                let mut extra = total - low;
+                let mut steps = Vec::with_capacity(count);
+                for (n, (lo, hi)) in [(u, up), (d, (-down.1, -down.0))] {
+                    for _ in 0..n {
+                        let raise = extra.min(hi - lo);
+                        extra -= raise;
+                        steps.push((lo + raise) as i32);
+                    }
+                }
+                steps.resize(count, 0);
+                return Some(steps);
+            }
+        }
+        None
+    }
+}
+
+fn repair_in(report: &[i32], costs: &RepairCosts, steps: &Steps) -> Option<Repair> {
+    let n = report.len();
+    if n == 0 {
+        return Some(Repair {
+            levels: vec![],
+            edited: vec![],
+            deleted: vec![],
+            cost: 0,
+        });
+    }
+    let edit = costs.edit.filter(|_| steps.single().is_some());
+    // levels outside the anchors: (cost each, whether to edit rather than delete)
+    let outer = match (edit, costs.delete) {
+        (Some(e), Some(d)) => Some((e.min(d), e <= d)),
+        (Some(e), None) => Some((e, true)),
+        (None, Some(d)) => Some((d, false)),
+        (None, None) => None,
+    };
+    let outer_cost = |count: usize| match (count, outer) {
+        (0, _) => Some(0),
+        (_, Some((cost, _))) => Some(count * cost),
+        (_, None) => None,
+    };
+    // the cheapest number of levels to edit between two anchors; the rest are deleted.
+    let gap = |i: usize, j: usize| {
+        let g = j - i - 1;
+        (0..=g)
+            .filter_map(|e| {
+                let cost = match (e, g - e) {
+                    (0, 0) => 0,
+                    (0, d) => d * costs.delete?,
+                    (e, 0) => e * edit?,
+                    (e, d) => e * edit? + d * costs.delete?,
+                };
+                steps.fill(report[j] - report[i], e + 1).map(|_| (cost, e))
+            })
+            .min()
+    };
+
+    // best[j]: cheapest repair of the levels up to anchor j, with the previous anchor and how
+    // many levels after it are edited.
+    let mut best = vec![None; n];
+    for j in 0..n {
+        best[j] = outer_cost(j).map(|cost| (cost, None));
+        for i in 0..j {
+            let Some((before, _)) = best[i] else {
+                continue;
+            };
+            if let Some((cost, e)) = gap(i, j) {
+                if best[j].is_none_or(|(c, _)| before + cost < c) {
+                    best[j] = Some((before + cost, Some((i, e))));
+                }
+            }
+        }
+    }
+    let (cost, last) = (0..n)
+        .filter_map(|j| Some((best[j]?.0 + outer_cost(n - 1 - j)?, j)))
+        .min()?;
+
+    let mut values: Vec<Option<i32>> = vec![None; n];
+    let mut edited = vec![];
+    let mut deleted = vec![];
+    let mut first = last;
+    loop {
+        values[first] = Some(report[first]);
+        let Some((i, e)) = best[first].unwrap().1 else {
+            break;
+        };
+        let fill = steps.fill(report[first] - report[i], e + 1).unwrap();
+        let mut value = report[i];
+        for (k, index) in (i + 1..first).enumerate() {
+            if k < e {
+                value += fill[k];
+                values[index] = Some(value);
+                edited.push(index);
+            } else {
+                deleted.push(index);
+            }
+        }
+        first = i;
+    }
+    // extend outwards from the first and last anchors by the smallest permitted step.
+    let step = steps.single().unwrap_or(0);
+    for index in (0..first).chain(last + 1..n) {
+        if outer.is_some_and(|(_, edit)| edit) {
+            values[index] = Some(if index < first {
+                report[first] - step * (first - index) as i32
+            } else {
+                report[last] + step * (index - last) as i32
+            });
+            edited.push(index);
+        } else {
+            deleted.push(index);
+        }
+    }
+    edited.sort_unstable();
+    deleted.sort_unstable();
+    Some(Repair {
+        levels: values.into_iter().flatten().collect(),
+        edited,
+        deleted,
+        cost,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportAnalysis {
+    /// Indices of a longest safe subsequence of the levels.
+    pub longest: Vec<usize>,
+    /// Every maximal contiguous safe run of levels. Runs may share their end levels, where the
+    /// report turns around or pauses on an equal step.
+    pub runs: Vec<Range<usize>>,
+}
+
+pub fn analyse(input: &[Vec<i32>]) -> Vec<ReportAnalysis> {
+    let policy = SafetyPolicy::default();
+    input
+        .iter()
+        .map(|report| analyse_report(report, &policy))
+        .collect()
+}
+
+pub fn analyse_report(report: &[i32], policy: &SafetyPolicy) -> ReportAnalysis {
+    ReportAnalysis {
+        longest: longest_safe_subsequence(report, policy),
+        runs: safe_runs(report, policy),
+    }
+}
+
+/// The indices of a longest subsequence of levels that is safe.
+///
+/// The best chain ending at each level extends the best chain ending at any earlier level a
+/// permitted step away. Earlier chains are looked up by value, so this is O(n * permitted
+/// steps), falling back to comparing against every earlier level when steps are unbounded.
+pub fn longest_safe_subsequence(report: &[i32], policy: &SafetyPolicy) -> Vec<usize> {
+    let modes: &[i32] = if policy.lock_direction { &[1, 2] } else { &[0] };
+    modes
+        .iter()
+        .map(|&dir| longest_in(report, &Steps::new(policy, dir)))
+        .max_by_key(Vec::len)
+        .unwrap_or_default()
+}
+
+fn longest_in(report: &[i32], steps: &Steps) -> Vec<usize> {
+    let n = report.len();
+    let offsets = steps.all(n);
+    let mut length = vec![1; n];
+    let mut previous = vec![None; n];
+    // the index of the longest chain so far that ends in each value.
+    let mut ending: HashMap<i32, usize> = HashMap::new();
+    for j in 0..n {
+        let best = match &offsets {
+            Some(offsets) => offsets
+                .iter()
+                .filter_map(|&step| ending.get(&(report[j] - step)).copied())
+                .max_by_key(|&i| length[i]),
+            None => (0..j)
+                .filter(|&i| steps.contains(report[j] - report[i]))
+                .max_by_key(|&i| length[i]),
+        };
+        if let Some(i) = best {
+            length[j] = length[i] + 1;
+            previous[j] = Some(i);
+        }
+        let end = ending.entry(report[j]).or_insert(j);
+        if length[j] > length[*end] {
+            *end = j;
+        }
+    }
+    let mut longest = vec![];
+    let mut j = (0..n).max_by_key(|&j| length[j]);
+    while let Some(i) = j {
+        longest.push(i);
+        j = previous[i];
+    }
+    longest.reverse();
+    longest
+}
+
+/// The maximal contiguous runs of safe levels, in O(n).
+///
+/// A window is safe while none of its steps is bad and it doesn't hold steps in both
+/// directions, so for each end level the earliest start of a safe window only moves forward.
+pub fn safe_runs(report: &[i32], policy: &SafetyPolicy) -> Vec<Range<usize>> {
+    // 1 increasing, 2 decreasing, 0 neither (an equal step, or any step when unlocked), 3 bad.
+    let steps = report
+        .windows(2)
+        .map(|pair| {
+            policy
+                .fold_pair(pair[0], pair[1], 0)
+                .map_or(3, |dir| dir as usize)
+        })
+        .collect::<Vec<_>>();
+    let mut counts = [0; 4];
+    let mut starts = Vec::with_capacity(report.len());
+    let mut start = 0;
+    for end in 0..report.len() {
+        if end > 0 {
+            counts[steps[end - 1]] += 1;
+        }
+        while counts[3] > 0 || (counts[1] > 0 && counts[2] > 0) {
+            counts[steps[start]] -= 1;
+            start += 1;
+        }
+        starts.push(start);
+    }
+    starts
+        .iter()
+        .enumerate()
+        .filter(|&(end, &start)| starts.get(end + 1).is_none_or(|&next| next > start))
+        .map(|(end, &start)| start..end + 1)
+        .collect()
+}
+
+/// How many ways the unknown levels of a report can be filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssignmentCounts {
+    pub safe: u128,
+    pub dampened: u128,
+}
+
+/// For each report, count the fillings of its unknown levels, each within `bound`, that make
+/// it safe with and without the Problem Dampener.
+pub fn count_assignments(
+    input: &[Vec<Option<i32>>],
+    bound: RangeInclusive<i32>,
+) -> Vec<AssignmentCounts> {
+    input
+        .iter()
+        .map(|report| AssignmentCounts {
+            safe: count_safe_assignments(report, bound.clone()),
+            dampened: count_dampened_assignments(report, bound.clone()),
+        })
+        .collect()
+}
+
+/// The interval each level can take in a safe report going in `direction` (1 or -1), or None
+/// if there is none. A forward pass bounds each level by the steps from the one before, and a
+/// backward pass by the steps to the one after, after which every value of every interval is
+/// part of some safe report.
+fn propagate(
+    report: &[Option<i32>],
+    bound: &RangeInclusive<i32>,
+    direction: i64,
+) -> Option<Vec<(i64, i64)>> {
+    // the values a level can step from, to reach the interval `to`.
+    let before = |(lo, hi): (i64, i64)| match direction {
+        1 => (lo - 3, hi - 1),
+        _ => (lo + 1, hi + 3),
+    };
+    let after = |(lo, hi): (i64, i64)| match direction {
+        1 => (lo + 1, hi + 3),
+        _ => (lo - 3, hi - 1),
+    };
+    let intersect = |a: (i64, i64), b: (i64, i64)| {
+        Some((a.0.max(b.0), a.1.min(b.1))).filter(|(lo, hi)| lo <= hi)
+    };
+    let mut intervals = report
+        .iter()
+        .map(|level| match level {
+            Some(v) => (*v as i64, *v as i64),
+            None => (*bound.start() as i64, *bound.end() as i64),
+        })
+        .collect::<Vec<_>>();
+    for i in 1..intervals.len() {
+        intervals[i] = intersect(intervals[i], after(intervals[i - 1]))?;
+    }
+    for i in (0..intervals.len().saturating_sub(1)).rev() {
+        intervals[i] = intersect(intervals[i], before(intervals[i + 1]))?;
+    }
+    Some(intervals)
+}
+
+/// Count the safe fillings of the unknown levels, without enumerating them. The number of safe
+/// prefixes ending in a value is the sum of those ending in the 3 values before it, so it only
+/// changes within 3 of where the counts for the previous level change. The counts are kept as
+/// runs of equal values, so a level costs time in its number of runs rather than its width.
+pub fn count_safe_assignments(report: &[Option<i32>], bound: RangeInclusive<i32>) -> u128 {
+    if bound.is_empty() && report.iter().any(Option::is_none) {
+        return 0;
+    }
+    if report.len() <= 1 {
+        return report
+            .iter()
+            .map(|l| l.map_or(bound.clone().count() as u128, |_| 1))
+            .product();
+    }
+    [1, -1]
+        .into_iter()
+        .filter_map(|direction| {
+            let intervals = propagate(report, &bound, direction)?;
+            // the previous values a value can step from, as offsets.
+            let (near, far) = match direction {
+                1 => (-1, -3),
+                _ => (1, 3),
+            };
+            let (from, to) = (near.min(far), near.max(far));
+            // (first value, last value, safe prefixes ending in each) for the current level.
+            let (lo, hi) = intervals[0];
+            let mut runs = vec![(lo, hi, 1u128)];
+            for &(lo, hi) in &intervals[1..] {
+                let count = |v: i64| {
+                    runs.iter()
+                        .map(|&(first, last, n)| {
+                            let overlap = last.min(v + to) - first.max(v + from) + 1;
+                            n * overlap.max(0) as u128
+                        })
+                        .sum::<u128>()
+                };
+                // the values where the count may differ from the value before.
+                let mut starts = runs
+                    .iter()
+                    .flat_map(|&(first, last, _)| [first, last + 1])
+                    .flat_map(|edge| edge - to..=edge - from)
+                    .chain([lo])
+                    .filter(|v| (lo..=hi).contains(v))
+                    .collect::<Vec<_>>();
+                starts.sort_unstable();
+                starts.dedup();
+                let ends = starts.iter().skip(1).map(|v| v - 1).chain([hi]);
+                let mut next = starts
+                    .iter()
+                    .zip(ends)
+                    .map(|(&first, last)| (first, last, count(first)))
+                    .collect::<Vec<_>>();
+                next.dedup_by(|run, before| {
+                    let equal = run.2 == before.2;
+                    if equal {
+                        before.1 = run.1;
+                    }
+                    equal
+                });
+                runs = next;
+            }
+            Some(
+                runs.iter()
+                    .map(|&(first, last, n)| n * (last - first + 1) as u128)
+                    .sum::<u128>(),
+            )
+        })
+        .sum()
+}
+
+/// Every safe filling of the report, from the propagated intervals. Each value of an interval
+/// extends to a safe report, so the search never has to backtrack.
+pub fn safe_assignments(report: &[Option<i32>], bound: RangeInclusive<i32>) -> Vec<Vec<i32>> {
+    fn extend(
+        intervals: &[(i64, i64)],
+        direction: i64,
+        prefix: &mut Vec<i32>,
+        out: &mut Vec<Vec<i32>>,
+    ) {
+        let Some(&(lo, hi)) = intervals.get(prefix.len()) else {
+            out.push(prefix.clone());
+            return;
+        };
+        let (lo, hi) = match prefix.last() {
+            Some(&last) if direction == 1 => (lo.max(last as i64 + 1), hi.min(last as i64 + 3)),
+            Some(&last) => (lo.max(last as i64 - 3), hi.min(last as i64 - 1)),
+            None => (lo, hi),
+        };
+        for v in lo..=hi {
+            prefix.push(v as i32);
+            extend(intervals, direction, prefix, out);
+            prefix.pop();
+        }
+    }
+
+    let mut out = vec![];
+    // a report of up to one level is safe either way, so only take it once.
+    let directions: &[i64] = if report.len() <= 1 { &[1] } else { &[1, -1] };
+    for &direction in directions {
+        if let Some(intervals) = propagate(report, &bound, direction) {
+            extend(&intervals, direction, &mut vec![], &mut out);
+        }
+    }
+    out
+}
+
+/// Count the fillings of the unknown levels that the Problem Dampener makes safe.
+///
+/// This runs a [`ReportClassifier`] over every filling at once, counting how many fillings
+/// reach each classifier state. An unknown level more than 3 from both recent levels can't be
+/// kept next to either, so once the report is under way all such values are counted together as
+/// one far level. Before then there are no recent levels, so the first two unknowns are grouped
+/// by the values they could keep in view of the levels after them.
+pub fn count_dampened_assignments(report: &[Option<i32>], bound: RangeInclusive<i32>) -> u128 {
+    let width = bound.clone().count() as u128;
+    if report.len() <= 2 {
+        // dropping a level always leaves a safe report.
+        return width.pow(report.iter().filter(|l| l.is_none()).count() as u32);
+    }
+    let keepable = keepable(report, &bound);
+    let mut states: HashMap<ReportClassifier, u128> =
+        HashMap::from([(ReportClassifier::default(), 1)]);
+    for (i, level) in report.iter().enumerate() {
+        let mut next = HashMap::new();
+        for (state, count) in states {
+            let mut push = |value: Option<i32>, ways: u128| {
+                let mut state = state.clone();
+                state.push_level(value);
+                let alive = state.levels <= 1
+                    || state
+                        .states
+                        .iter()
+                        .flatten()
+                        .any(|&(clean, dampened)| clean || dampened);
+                if alive && ways > 0 {
+                    *next.entry(state).or_insert(0) += count * ways;
+                }
+            };
+            match level {
+                Some(value) => push(Some(*value), 1),
+                None if i < 2 => {
+                    // an empty range when no value can be kept.
+                    let (lo, hi) = keepable[i].unwrap_or((1, 0));
+                    let values = lo..=hi;
+                    values.clone().for_each(|value| push(Some(value as i32), 1));
+                    push(None, width - values.count() as u128);
+                }
+                None => {
+                    // nothing can be kept next to a far level, so it has no near values.
+                    let mut values = state
+                        .last
+                        .into_iter()
+                        .flatten()
+                        .flat_map(|v| {
+                            (v as i64 - 3).max(*bound.start() as i64)
+                                ..=(v as i64 + 3).min(*bound.end() as i64)
+                        })
+                        .collect::<Vec<_>>();
+                    values.sort_unstable();
+                    values.dedup();
+                    values.iter().for_each(|&value| push(Some(value as i32), 1));
+                    push(None, width - values.len() as u128);
+                }
+            }
+        }
+        states = next;
+    }
+    states
+        .into_iter()
+        .filter(|(state, _)| state.classify() != Classification::Unsafe)
+        .map(|(_, count)| count)
+        .sum()
+}
+
+/// For each level, an interval holding every value it could keep in a dampened safe report, or
+/// None if it can keep none. Until the last two levels one of the next two is always kept, so a
+/// kept level is within 3 of a value one of them can keep; any other value has to be dropped.
+fn keepable(report: &[Option<i32>], bound: &RangeInclusive<i32>) -> Vec<Option<(i64, i64)>> {
+    let intersect = |a: (i64, i64), b: (i64, i64)| {
+        Some((a.0.max(b.0), a.1.min(b.1))).filter(|(lo, hi)| lo <= hi)
+    };
+    let mut keepable = report
+        .iter()
+        .map(|level| match level {
+            Some(v) => Some((*v as i64, *v as i64)),
+            None => Some((*bound.start() as i64, *bound.end() as i64)).filter(|(lo, hi)| lo <= hi),
+        })
+        .collect::<Vec<_>>();
+    for i in (0..report.len().saturating_sub(2)).rev() {
+        let reach = [keepable[i + 1], keepable[i + 2]]
+            .into_iter()
+            .flatten()
+            .map(|(lo, hi)| (lo - 3, hi + 3))
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+        keepable[i] = keepable[i]
+            .zip(reach)
+            .and_then(|(own, reach)| intersect(own, reach));
+    }
+    keepable
+}
+
+/// A day2 solution, as its part_1 and part_2 functions.
+pub type Parts = (fn(&[Vec<i32>]) -> usize, fn(&[Vec<i32>]) -> usize);
+
+/// Classify one report by running both parts of a solution over it alone.
+pub fn classify_by(parts: Parts, report: &[i32]) -> Classification {
+    let input = [report.to_vec()];
+    match (parts.0(&input), parts.1(&input)) {
+        (1, _) => Classification::Safe,
+        (_, 1) => Classification::DampenerSafe,
+        _ => Classification::Unsafe,
+    }
+}
+
+/// A report that two classifiers disagree on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disagreement {
+    pub report: Vec<i32>,
+    /// What the reference classifier said.
+    pub expected: Classification,
+    pub actual: Classification,
+}
+
+impl Disagreement {
+    /// The report as a row of the `test_records` table, with the reference answers.
+    pub fn test_record(&self) -> String {
+        let levels = self.report.iter().map(i32::to_string).collect::<Vec<_>>();
+        let (part_1, part_2) = match self.expected {
+            Classification::Safe => (1, 1),
+            Classification::DampenerSafe => (0, 1),
+            Classification::Unsafe => (0, 0),
+        };
+        format!(
+            "(\"{}\", {part_1}, {part_2}), // not {:?}",
+            levels.join(" "),
+            self.actual
+        )
+    }
+}
+
+/// Run `actual` against the `expected` reference over `reports`, and shrink the first report
+/// they disagree on to a minimal one.
+pub fn differential(
+    expected: impl Fn(&[i32]) -> Classification,
+    actual: impl Fn(&[i32]) -> Classification,
+    reports: impl IntoIterator<Item = Vec<i32>>,
+) -> Option<Disagreement> {
+    let disagree = |report: &[i32]| expected(report) != actual(report);
+    let report = reports.into_iter().find(|report| disagree(report))?;
+    let report = minimise(report, disagree);
+    Some(Disagreement {
+        expected: expected(&report),
+        actual: actual(&report),
+        report,
+    })
+}
+
+/// Shrink a report that `fails` to one that still fails, where removing any level or moving
+/// any value towards zero no longer does.
+///
+/// Levels go by delta debugging: drop ever smaller chunks while the report keeps failing.
+/// Values then shift together towards zero, which keeps every step, and finally each value is
+/// pulled towards zero on its own.
+pub fn minimise(mut report: Vec<i32>, fails: impl Fn(&[i32]) -> bool) -> Vec<i32> {
+    loop {
+        let before = report.clone();
+        let mut chunk = report.len() / 2;
+        while chunk > 0 {
+            let mut start = 0;
+            while start < report.len() {
+                let mut candidate = report.clone();
+                candidate.drain(start..(start + chunk).min(report.len()));
+                if fails(&candidate) {
+                    report = candidate;
+                } else {
+                    start += chunk;
+                }
+            }
+            chunk /= 2;
+        }
+        // the smallest value is the one to bring to zero without going past it, unless that
+        // takes another value out of range.
+        if let Some(&shift) = report.iter().min_by_key(|v| v.unsigned_abs()) {
+            let candidate = report
+                .iter()
+                .map(|v| v.checked_sub(shift))
+                .collect::<Option<Vec<_>>>();
+            if let Some(candidate) = candidate.filter(|c| shift != 0 && fails(c)) {
+                report = candidate;
+            }
+        }
+        for i in 0..report.len() {
+            // nearest to zero first, so the first that fails is the one to keep.
+            let v = report[i];
+            for towards in [0, v / 2, v - v.signum()] {
+                let mut candidate = report.clone();
+                candidate[i] = towards;
+                if towards != v && fails(&candidate) {
+                    report = candidate;
+                    break;
+                }
+            }
+        }
+        if report == before {
+            return report;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -184,135 +1449,669 @@ mod tests {
             let input = generate(row.0);
             assert_eq!(part_1(&input), row.1, "{:?}", row.0);
             assert_eq!(part_2(&input), row.2, "{:?}", row.0);
+            assert_eq!(part_2_single_pass(&input), row.2, "{:?}", row.0);
+            assert_eq!(part_1_dp(&input), row.1, "{:?}", row.0);
+            assert_eq!(part_2_dp(&input), row.2, "{:?}", row.0);
+            assert_eq!(part_1_rayon(&input), row.1, "{:?}", row.0);
+            assert_eq!(part_2_rayon(&input), row.2, "{:?}", row.0);
+            assert_eq!(part_1_flat(&input), row.1, "{:?}", row.0);
+            assert_eq!(part_2_flat(&input), row.2, "{:?}", row.0);
         }
     }
 
-    #[derive(Debug, Clone)]
-    enum Outcomes {
-        Failure,
-        SuccessWithoutErrors,
-        SuccessWithErrors,
+    #[test]
+    fn test_differential() {
+        // dampens by dropping the right-hand level of the first bad step, missing the left.
+        let naive = |report: &[i32]| {
+            let first_bad =
+                (1..report.len()).find(|&i| !SafetyPolicy::PUZZLE.is_safe(&report[..=i]));
+            match first_bad {
+                None => Classification::Safe,
+                Some(i) => {
+                    let mut report = report.to_vec();
+                    report.remove(i);
+                    if SafetyPolicy::PUZZLE.is_safe(&report) {
+                        Classification::DampenerSafe
+                    } else {
+                        Classification::Unsafe
+                    }
+                }
+            }
+        };
+        let brute_force = |report: &[i32]| classify_by((part_1, part_2), report);
+        let mut generator = ReportGenerator::new(1);
+        let reports = (0..1000).map(|_| generator.random_report(12).levels);
+        let found = differential(brute_force, naive, reports).unwrap();
+        assert_eq!(brute_force(&found.report), found.expected);
+        assert_ne!(found.expected, found.actual);
+        // the first bad step is 0 1, but it's the first level that has to go.
+        assert_eq!(found.test_record(), "(\"1 0 1 2\", 0, 1), // not Unsafe");
+
+        let reports = (0..1000).map(|_| generator.random_report(12).levels);
+        assert_eq!(
+            differential(
+                brute_force,
+                |r: &[i32]| classify_by((part_1_dp, part_2_dp), r),
+                reports
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_minimise_extremes() {
+        // shifting by i32::MAX would take i32::MIN out of range, so only the values move.
+        let fails = |r: &[i32]| r.len() >= 2 && r.contains(&i32::MIN);
+        assert_eq!(minimise(vec![i32::MAX, i32::MIN], fails), vec![0, i32::MIN]);
+        let fails = |r: &[i32]| r.len() >= 2 && r[0] - r[1] == 1;
+        // i32::MIN + 1 is nearer zero, so that's the shift.
+        assert_eq!(minimise(vec![i32::MIN + 1, i32::MIN], fails), vec![0, -1]);
+    }
+
+    #[test]
+    fn test_flat_boundary_overflow() {
+        let input = [
+            vec![2_000_000_000, 2_000_000_001],
+            vec![-2_000_000_000, -1_999_999_999],
+        ];
+        assert_eq!(part_1_flat(&input), 2);
+        assert_eq!(part_2_flat(&input), 2);
+
+        // the same extremes within one report.
+        let input = [
+            vec![-2_000_000_000, 2_000_000_000],
+            vec![i32::MIN, i32::MAX, i32::MIN],
+            vec![1, 2, i32::MAX, 3],
+        ];
+        assert_eq!(part_1(&input), 0);
+        assert_eq!(part_1_flat(&input), 0);
+        assert_eq!(part_1_dp(&input), 0);
+        assert_eq!(part_2(&input), 2);
+        assert_eq!(part_2_single_pass(&input), 2);
+        assert_eq!(part_2_flat(&input), 2);
+        assert_eq!(part_2_dp(&input), 2);
+        let text = "-2000000000 2000000000\n-2147483648 2147483647 -2147483648\n1 2 2147483647 3\n";
+        assert_eq!(count_stream(text.as_bytes()).unwrap(), (0, 2));
+    }
+
+    /// Every way of filling the unknown levels from the bound.
+    fn fillings(report: &[Option<i32>], bound: &RangeInclusive<i32>) -> Vec<Vec<i32>> {
+        report.iter().fold(vec![vec![]], |fillings, level| {
+            let values = level.map_or(bound.clone(), |v| v..=v);
+            fillings
+                .iter()
+                .flat_map(|f| values.clone().map(move |v| [f.as_slice(), &[v]].concat()))
+                .collect()
+        })
     }
 
-    #[derive(Debug, Clone)]
-    enum LevelError {
-        Direction,
-        Size(usize),
+    #[test]
+    fn test_wide_leading_wildcards() {
+        let report = [None, None, Some(5), Some(6)];
+        let count = |bound| count_dampened_assignments(&report, bound);
+        let brute_force = fillings(&report, &(0..=60))
+            .iter()
+            .filter(|f| dampen(f, 1).is_some())
+            .count() as u128;
+        assert_eq!(count(0..=60), brute_force);
+        // each further value is too far from 5 and 6 to keep, so is dropped ahead of 3 safe
+        // fillings of the other unknown, in either place.
+        assert_eq!(count(0..=20_000), brute_force + 6 * (20_000 - 60));
     }
 
-    #[derive(Debug, Clone)]
-    struct BadLevel {
-        interval: usize,
-        kind: LevelError,
+    #[test]
+    fn test_wide_safe_wildcards() {
+        // 3 steps up or down from every value, less those that leave the bound.
+        let width = 200_000_001;
+        assert_eq!(
+            count_safe_assignments(&[None, None], 0..=200_000_000),
+            6 * width - 12
+        );
+        let report = [None, Some(100), None, None, Some(105), None];
+        let brute_force = fillings(&report, &(80..=130))
+            .iter()
+            .filter(|f| part_1(&[f.to_vec()]) == 1)
+            .count() as u128;
+        assert_eq!(count_safe_assignments(&report, 80..=130), brute_force);
+        assert_eq!(
+            count_safe_assignments(&report, i32::MIN..=i32::MAX),
+            brute_force
+        );
     }
 
-    fn arb_bad_level() -> impl Strategy<Value = BadLevel> {
-        let level_error = prop_oneof![
+    #[test]
+    fn test_far_wildcards() {
+        // dropping the unknown leaves a safe report, whatever it is.
+        let report = [
+            Some(2_000_000_000),
+            Some(2_000_000_001),
+            None,
+            Some(2_000_000_002),
+        ];
+        assert_eq!(
+            count_dampened_assignments(&report, 0..=2_100_000_000),
+            2_100_000_001
+        );
+        // far values must not be mistaken for real ones near them, here i32::MIN / 2.
+        let m = -1_073_741_824;
+        let report = [Some(m + 1), Some(m + 2), None, Some(m - 1)];
+        let bound = m - 20..=m + 20;
+        let brute_force = fillings(&report, &bound)
+            .iter()
+            .filter(|f| dampen(f, 1).is_some())
+            .count() as u128;
+        assert_eq!(count_dampened_assignments(&report, bound), brute_force);
+    }
+
+    fn arb_level_error() -> impl Strategy<Value = LevelError> {
+        prop_oneof![
             Just(LevelError::Direction),
             // size of 0 is an invalid step, as is a too-large step.
             prop_oneof![Just(0usize), 4usize..10].prop_map(LevelError::Size)
-        ];
-        (0usize..4, level_error).prop_map(|(interval, kind)| dbg!(BadLevel { interval, kind }))
+        ]
     }
 
-    fn apply_error(v: &mut Vec<i32>, bad: &BadLevel, direction: i32) {
-        assert_ne!(direction, 0);
-        match bad.kind {
-            LevelError::Direction => {
-                let existing_step = v[bad.interval + 1] - v[bad.interval];
-                let delta = -existing_step - direction;
-                for i in (bad.interval + 1)..v.len() {
-                    v[i] = v[i] + delta;
+    /// Try every choice of keeping, editing or deleting each level, searching edited values in
+    /// a range wide enough for the small reports it is used with.
+    fn repair_oracle(report: &[i32], policy: &SafetyPolicy, costs: &RepairCosts) -> Option<usize> {
+        let domain = -40..=52;
+        (0..3usize.pow(report.len() as u32))
+            .filter_map(|mut choices| {
+                let mut cost = 0;
+                let mut kept = vec![];
+                for &level in report {
+                    match choices % 3 {
+                        0 => kept.push(Some(level)),
+                        1 => {
+                            cost += costs.edit?;
+                            kept.push(None);
+                        }
+                        _ => cost += costs.delete?,
+                    }
+                    choices /= 3;
                 }
-            }
-            LevelError::Size(size) => {
-                // calulate the change needed to make the interval the given size
-                eprintln!("size: {size}, {v:?}, {bad:?}");
-                let delta = (direction * size as i32) - (v[bad.interval + 1] - v[bad.interval]);
-                // rewrite the array from the index to the end preserving the existing deltas.
-                for i in (bad.interval + 1)..v.len() {
-                    v[i] = v[i] + delta;
+                // reachable (value, direction bits) after each kept level.
+                let mut reachable = vec![(None, 0)];
+                for level in kept {
+                    let candidates = level.map_or(domain.clone().collect(), |v| vec![v]);
+                    let mut next = candidates
+                        .into_iter()
+                        .flat_map(|value| {
+                            reachable.iter().filter_map(move |&(prev, dir)| match prev {
+                                None => Some((Some(value), dir)),
+                                Some(prev) => policy
+                                    .fold_pair(prev, value, dir)
+                                    .ok()
+                                    .map(|dir| (Some(value), dir)),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    next.sort_unstable();
+                    next.dedup();
+                    reachable = next;
                 }
-            }
-        }
+                (!reachable.is_empty()).then_some(cost)
+            })
+            .min()
     }
 
-    fn arb_record() -> impl Strategy<Value = (Vec<i32>, usize, usize, BadLevel, BadLevel, i32)> {
-        (
-            prop_oneof![Just(1), Just(-1),],
-            0i32..100,
-            prop_oneof![
-                Just(Outcomes::Failure),
-                Just(Outcomes::SuccessWithoutErrors),
-                Just(Outcomes::SuccessWithErrors)
-            ],
-            // single-error
-            arb_bad_level(),
-            // second error offset (0..3 + 1..4 % 4 is unique)
-            1usize..4,
-            // second error details
-            arb_bad_level(),
-        )
-            .prop_map(
-                |(direction, start, outcome, single_error, second_offset, mut second_error)| {
-                    second_error.interval = (single_error.interval + second_offset) % 5;
-                    (direction, start, outcome, single_error, second_error)
-                },
-            )
-            .prop_map(|(direction, start, outcome, single_error, second_error)| {
-                assert_ne!(single_error.interval, second_error.interval);
-                let mut v = vec![start; 5];
-                for i in 1..v.len() {
-                    v[i] = v[i - 1] + 2 * direction;
-                }
-                match outcome {
-                    Outcomes::SuccessWithoutErrors => {}
-                    Outcomes::SuccessWithErrors => {
-                        apply_error(&mut v, &single_error, direction);
-                    }
-                    Outcomes::Failure => {
-                        apply_error(&mut v, &single_error, direction);
-                        apply_error(&mut v, &second_error, direction);
-                    }
-                }
-                let &min = v.iter().min().unwrap();
-                if min < 0 {
-                    for i in 0..v.len() {
-                        v[i] = v[i] - min;
-                    }
-                }
-                for i in 0..v.len() {
-                    if v[i] < 0 {
-                        v[i] = 0;
-                    }
-                }
-                dbg!((
-                    v,
-                    match outcome {
-                        Outcomes::SuccessWithoutErrors => 1,
-                        _ => 0,
-                    },
-                    match outcome {
-                        Outcomes::Failure => 0,
-                        _ => 1,
-                    },
-                    single_error,
-                    second_error,
-                    direction
-                ))
+    /// A report of up to 20 levels with up to three injected faults.
+    fn arb_record() -> impl Strategy<Value = GeneratedReport> {
+        (0usize..=3)
+            .prop_flat_map(|faults| {
+                (
+                    any::<u64>(),
+                    ReportGenerator::min_len(faults)..=20,
+                    prop::collection::vec(arb_level_error(), faults),
+                )
             })
+            .prop_map(|(seed, len, faults)| ReportGenerator::new(seed).report(len, &faults))
     }
 
     proptest! {
 
         #[test]
-        #[ignore]
-        fn test_arb_records(i in arb_record()) {
-            let (input, success, success_with_skip, _err_1, _err_2, _direction) = i;
-            let input = vec![input];
+        fn test_arb_records(report in arb_record()) {
+            let (success, success_with_skip) = match report.classification {
+                Classification::Safe => (1, 1),
+                Classification::DampenerSafe => (0, 1),
+                Classification::Unsafe => (0, 0),
+            };
+            let input = vec![report.levels];
             prop_assert_eq!(part_1(&input), success, "{:?}", input);
             prop_assert_eq!(part_2(&input), success_with_skip, "{:?}", input);
+            prop_assert_eq!(part_2_single_pass(&input), success_with_skip, "{:?}", input);
+            prop_assert_eq!(part_1_dp(&input), success, "{:?}", input);
+            prop_assert_eq!(part_2_dp(&input), success_with_skip, "{:?}", input);
+            let diagnosis = diagnose_report(&input[0], &SafetyPolicy::default());
+            prop_assert_eq!(diagnosis.first_error.is_none(), success == 1);
+            prop_assert_eq!(diagnosis.safety != Safety::Unsafe, success_with_skip == 1);
+        }
+
+        #[test]
+        fn test_single_pass_matches_brute_force(
+            input in prop::collection::vec(prop::collection::vec(0i32..12, 0..12), 0..8)
+        ) {
+            prop_assert_eq!(part_2_single_pass(&input), part_2(&input), "{:?}", input);
+        }
+
+        #[test]
+        fn test_minimise_keeps_failing(
+            (mut report, bad) in prop::collection::vec(-20i32..20, 1..12)
+                .prop_flat_map(|r| { let len = r.len(); (Just(r), 0..len) }),
+        ) {
+            // fails whenever the report is unsafe, so minimal is a single bad step.
+            let fails = |r: &[i32]| !SafetyPolicy::PUZZLE.is_safe(r);
+            // repeating a level makes a zero step, so every report is unsafe to begin with.
+            report.insert(bad, report[bad]);
+            let minimal = minimise(report, fails);
+            prop_assert!(fails(&minimal));
+            prop_assert_eq!(minimal.len(), 2);
+            prop_assert!(minimal.contains(&0));
         }
+
+        #[test]
+        fn test_variants_match(
+            input in prop::collection::vec(prop::collection::vec(0i32..12, 0..12), 0..20)
+        ) {
+            let (safe, dampened) = (part_1(&input), part_2(&input));
+            prop_assert_eq!(part_1_rayon(&input), safe);
+            prop_assert_eq!(part_2_rayon(&input), dampened);
+            prop_assert_eq!(part_1_flat(&input), safe);
+            prop_assert_eq!(part_2_flat(&input), dampened);
+            let flat = FlatReports::from(input.as_slice());
+            prop_assert_eq!(flat.reports().collect::<Vec<_>>(), input.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn test_policy_dampen_matches_brute_force(
+            input in prop::collection::vec(prop::collection::vec(0i32..12, 0..10), 0..8),
+            (min_step, max_step) in (0i32..3, 0i32..5),
+            (increasing, decreasing, allow_equal, lock_direction) in any::<(bool, bool, bool, bool)>(),
+        ) {
+            let policy = SafetyPolicy { min_step, max_step, increasing, decreasing, allow_equal, lock_direction };
+            prop_assert_eq!(count_dampened_with(&input, 0, &policy), part_1_with(&input, &policy));
+            prop_assert_eq!(count_dampened_with(&input, 1, &policy), part_2_with(&input, &policy));
+        }
+
+        #[test]
+        fn test_analysis_matches_brute_force(
+            report in prop::collection::vec(0i32..12, 0..10),
+            (min_step, max_step) in (0i32..3, 0i32..5),
+            (increasing, decreasing, allow_equal, lock_direction) in any::<(bool, bool, bool, bool)>(),
+        ) {
+            let policy = SafetyPolicy { min_step, max_step, increasing, decreasing, allow_equal, lock_direction };
+            let longest = longest_safe_subsequence(&report, &policy);
+            let levels = longest.iter().map(|&i| report[i]).collect::<Vec<_>>();
+            prop_assert!(policy.is_safe(&levels));
+            prop_assert!(longest.windows(2).all(|w| w[0] < w[1]));
+            let fewest = dampen_with(&report, report.len(), &policy).unwrap().len();
+            prop_assert_eq!(longest.len(), report.len() - fewest);
+
+            let maximal = (0..report.len())
+                .flat_map(|start| (start + 1..=report.len()).map(move |end| start..end))
+                .filter(|run| policy.is_safe(&report[run.clone()]))
+                .collect::<Vec<_>>();
+            let maximal = maximal
+                .iter()
+                .filter(|run| {
+                    !maximal.iter().any(|other| {
+                        other != *run && other.start <= run.start && run.end <= other.end
+                    })
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            prop_assert_eq!(safe_runs(&report, &policy), maximal);
+        }
+
+        #[test]
+        fn test_assignments_match_brute_force(
+            report in prop::collection::vec(prop::option::of(0i32..8), 0..6),
+            (lo, width) in (0i32..8, 0i32..7),
+        ) {
+            let bound = lo..=lo + width - 1;
+            let fillings = fillings(&report, &bound);
+            let safe = fillings.iter().filter(|f| SafetyPolicy::PUZZLE.is_safe(f)).count() as u128;
+            let dampened = fillings.iter().filter(|f| dampen(f, 1).is_some()).count() as u128;
+            prop_assert_eq!(count_safe_assignments(&report, bound.clone()), safe);
+            prop_assert_eq!(count_dampened_assignments(&report, bound.clone()), dampened);
+            let mut enumerated = safe_assignments(&report, bound);
+            enumerated.sort();
+            let mut expected = fillings.into_iter().filter(|f| SafetyPolicy::PUZZLE.is_safe(f)).collect::<Vec<_>>();
+            expected.sort();
+            prop_assert_eq!(enumerated, expected);
+        }
+
+        #[test]
+        fn test_wide_assignments_match_brute_force(
+            leading in 1usize..3,
+            known in prop::collection::vec(20i32..30, 1..4),
+        ) {
+            // far more values than a step can span, so most are counted together.
+            let report = vec![None; leading].into_iter().chain(known.into_iter().map(Some)).collect::<Vec<_>>();
+            let fillings = fillings(&report, &(0..=50));
+            let dampened = fillings.iter().filter(|f| dampen(f, 1).is_some()).count() as u128;
+            prop_assert_eq!(count_dampened_assignments(&report, 0..=50), dampened);
+        }
+
+        #[test]
+        fn test_repair_is_minimal(
+            report in prop::collection::vec(0i32..12, 0..6),
+            (edit, delete) in (prop::option::of(1usize..4), prop::option::of(1usize..4)),
+            (min_step, max_step) in (0i32..3, 0i32..5),
+            (increasing, decreasing, allow_equal, lock_direction) in any::<(bool, bool, bool, bool)>(),
+        ) {
+            let policy = SafetyPolicy { min_step, max_step, increasing, decreasing, allow_equal, lock_direction };
+            let costs = RepairCosts { edit, delete };
+            let expected = repair_oracle(&report, &policy, &costs);
+            let repaired = repair_with(&report, &policy, &costs);
+            prop_assert_eq!(repaired.as_ref().map(|r| r.cost), expected);
+            if let Some(repaired) = repaired {
+                prop_assert!(policy.is_safe(&repaired.levels), "{:?}", repaired);
+                prop_assert_eq!(
+                    repaired.cost,
+                    repaired.edited.len() * edit.unwrap_or(0) + repaired.deleted.len() * delete.unwrap_or(0)
+                );
+                prop_assert_eq!(repaired.levels.len() + repaired.deleted.len(), report.len());
+                let kept = (0..report.len())
+                    .filter(|i| !repaired.deleted.contains(i))
+                    .collect::<Vec<_>>();
+                for (&index, &value) in kept.iter().zip(&repaired.levels) {
+                    prop_assert_eq!(value == report[index], !repaired.edited.contains(&index));
+                }
+            }
+        }
+
+        #[test]
+        fn test_dampen_is_minimal(report in prop::collection::vec(0i32..12, 0..10), k in 0usize..4) {
+            let safe = |mask: u32| {
+                let kept = (0..report.len())
+                    .filter(|&i| mask & (1 << i) == 0)
+                    .map(|i| report[i])
+                    .collect::<Vec<_>>();
+                kept.windows(2)
+                    .try_fold(0, |dir, pair| fold_pair(pair[0], pair[1], dir))
+                    .is_ok()
+            };
+            let fewest = (0..1u32 << report.len())
+                .filter(|&mask| safe(mask))
+                .map(|mask| mask.count_ones() as usize)
+                .min()
+                .unwrap();
+            match dampen(&report, k) {
+                Some(indices) => {
+                    prop_assert_eq!(indices.len(), fewest);
+                    prop_assert!(safe(indices.iter().map(|i| 1 << i).sum()));
+                }
+                None => prop_assert!(fewest > k),
+            }
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let relaxed = SafetyPolicy {
+            min_step: 2,
+            max_step: 5,
+            allow_equal: true,
+            ..SafetyPolicy::default()
+        };
+        let increasing = SafetyPolicy {
+            decreasing: false,
+            ..SafetyPolicy::default()
+        };
+        let unlocked = SafetyPolicy {
+            lock_direction: false,
+            ..SafetyPolicy::default()
+        };
+        for (report, puzzle, relaxed_ok, increasing_ok, unlocked_ok) in [
+            ("7 6 4 2 1", true, false, false, true),
+            ("1 3 6 7 9", true, false, true, true),
+            ("1 3 3 8 10", false, true, false, false),
+            ("1 3 2 4 5", false, false, false, true),
+            ("1 2 7 8 9", false, false, false, false),
+        ] {
+            let input = generate(report);
+            assert_eq!(part_1_with(&input, &SafetyPolicy::PUZZLE), puzzle as usize);
+            assert_eq!(
+                part_1_with(&input, &relaxed),
+                relaxed_ok as usize,
+                "{report}"
+            );
+            assert_eq!(
+                part_1_with(&input, &increasing),
+                increasing_ok as usize,
+                "{report}"
+            );
+            assert_eq!(
+                part_1_with(&input, &unlocked),
+                unlocked_ok as usize,
+                "{report}"
+            );
+        }
+        let input = generate("9 1 2 3 4");
+        assert_eq!(part_2_with(&input, &increasing), 1);
+        assert_eq!(count_dampened_with(&input, 1, &increasing), 1);
+        assert_eq!(dampen_with(&[1, 2, 9, 3], 1, &increasing), Some(vec![2]));
+        assert_eq!(dampen_with(&[4, 2, 3], 1, &increasing), Some(vec![0]));
+        assert_eq!(dampen_with(&[4, 3, 2], 1, &increasing), None);
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let input = generate("7 6 4 2 1\n1 2 7 8 9\n1 3 2 4 5\n8 6 4 4 1\n");
+        assert_eq!(
+            diagnose(&input),
+            vec![
+                Diagnosis {
+                    safety: Safety::Safe,
+                    first_error: None,
+                },
+                Diagnosis {
+                    safety: Safety::Unsafe,
+                    first_error: Some(BadLevel {
+                        interval: 1,
+                        kind: LevelError::Size(5),
+                    }),
+                },
+                Diagnosis {
+                    safety: Safety::Dampened(2),
+                    first_error: Some(BadLevel {
+                        interval: 1,
+                        kind: LevelError::Direction,
+                    }),
+                },
+                Diagnosis {
+                    safety: Safety::Dampened(3),
+                    first_error: Some(BadLevel {
+                        interval: 2,
+                        kind: LevelError::Size(0),
+                    }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dampen() {
+        assert_eq!(dampen(&[1, 3, 2, 4, 5], 0), None);
+        assert_eq!(dampen(&[1, 3, 2, 4, 5], 1), Some(vec![2]));
+        assert_eq!(dampen(&[1, 2, 9, 9, 3, 4], 1), None);
+        assert_eq!(dampen(&[1, 2, 9, 9, 3, 4], 2), Some(vec![2, 3]));
+        assert_eq!(dampen(&[9, 1, 2, 3, 4], 1), Some(vec![0]));
+        assert_eq!(dampen(&[1, 2, 3, 4, 9], 1), Some(vec![4]));
+        assert_eq!(dampen(&[], 0), Some(vec![]));
+    }
+
+    #[test]
+    fn test_generated_input() {
+        let generated = ReportGenerator::new(2024).input(500, 12);
+        let input = generate(&generated.text);
+        assert_eq!(input.len(), 500);
+        assert_eq!(part_1(&input), generated.part_1);
+        assert_eq!(part_2(&input), generated.part_2);
+        assert!(0 < generated.part_1 && generated.part_1 < generated.part_2);
+        assert!(generated.part_2 < 500);
+        assert_eq!(
+            ReportGenerator::new(2024).input(500, 12).text,
+            generated.text
+        );
+    }
+
+    #[test]
+    fn test_repair() {
+        let policy = SafetyPolicy::default();
+        let repaired = repair(&[7, 6, 4, 2, 1], &policy).unwrap();
+        assert_eq!((repaired.levels, repaired.cost), (vec![7, 6, 4, 2, 1], 0));
+        let repaired = repair(&[1, 2, 7, 8, 9], &policy).unwrap();
+        assert_eq!(repaired.cost, 1);
+        assert!(policy.is_safe(&repaired.levels));
+        let repaired = repair(&[1, 5, 9, 13, 2], &policy).unwrap();
+        assert_eq!(repaired.cost, 3);
+        assert!(policy.is_safe(&repaired.levels));
+        let repaired = repair(&[9, 9, 9, 13], &policy).unwrap();
+        assert_eq!(repaired.cost, 2);
+        assert!(policy.is_safe(&repaired.levels));
+        // with no permitted steps only a single level can remain.
+        let stuck = SafetyPolicy {
+            increasing: false,
+            decreasing: false,
+            ..policy
+        };
+        assert_eq!(repair(&[1, 2], &stuck), None);
+        let deletions = RepairCosts {
+            edit: Some(1),
+            delete: Some(1),
+        };
+        assert_eq!(
+            repair_with(&[1, 2], &stuck, &deletions)
+                .unwrap()
+                .levels
+                .len(),
+            1
+        );
+
+        // deleting the 9 is cheaper than editing it and the level after.
+        let costs = RepairCosts {
+            edit: Some(2),
+            delete: Some(3),
+        };
+        let repaired = repair_with(&[1, 2, 9, 3, 4], &policy, &costs).unwrap();
+        assert_eq!((repaired.deleted, repaired.cost), (vec![2], 3));
+        assert_eq!(repaired.levels, vec![1, 2, 3, 4]);
+        let repaired = repair_with(&[1, 2, 9, 5, 6], &policy, &costs).unwrap();
+        assert_eq!((repaired.edited, repaired.cost), (vec![2], 2));
+    }
+
+    #[test]
+    fn test_analyse() {
+        let input = generate("1 2 3 4 2 1\n1 2 9 3 4 20 21\n5 6 10\n");
+        assert_eq!(
+            analyse(&input),
+            vec![
+                ReportAnalysis {
+                    longest: vec![0, 1, 2, 3],
+                    runs: vec![0..4, 3..6],
+                },
+                ReportAnalysis {
+                    longest: vec![0, 1, 3, 4],
+                    runs: vec![0..2, 2..3, 3..5, 5..7],
+                },
+                ReportAnalysis {
+                    longest: vec![0, 1],
+                    runs: vec![0..2, 2..3],
+                },
+            ]
+        );
+        let equal = SafetyPolicy {
+            allow_equal: true,
+            ..SafetyPolicy::default()
+        };
+        assert_eq!(safe_runs(&[1, 2, 2, 1], &equal), vec![0..3, 1..4]);
+        let wide = SafetyPolicy {
+            max_step: i32::MAX,
+            ..SafetyPolicy::default()
+        };
+        assert_eq!(
+            longest_safe_subsequence(&[1, 2, 9, 3, 4, 20], &wide),
+            vec![0, 1, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_stream() {
+        let text = "7 6 4 2 1\n1 2 7 8 9\r\n9 7 6 2 1\n1 3 2 4 5\n\n8 6 4 4 1\n1 3 6 7 9";
+        let classifications = classify_stream(text.as_bytes())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            classifications,
+            vec![
+                Classification::Safe,
+                Classification::Unsafe,
+                Classification::Unsafe,
+                Classification::DampenerSafe,
+                Classification::DampenerSafe,
+                Classification::Safe,
+            ]
+        );
+        assert_eq!(count_stream(text.as_bytes()).unwrap(), (2, 4));
+        assert_eq!(count_stream("+1 -1 -2\n".as_bytes()).unwrap(), (1, 1));
+        assert!(count_stream("1 2 x\n".as_bytes()).is_err());
+        assert!(count_stream("1 2 3000000000\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_stream_matches_parts() {
+        let generated = ReportGenerator::new(38).input(2000, 30);
+        let input = generate(&generated.text);
+        let expected = (part_1(&input), part_2(&input));
+        assert_eq!(expected, (generated.part_1, generated.part_2));
+        assert_eq!(count_stream(generated.text.as_bytes()).unwrap(), expected);
+        assert_eq!((part_1_rayon(&input), part_2_rayon(&input)), expected);
+        assert_eq!((part_1_flat(&input), part_2_flat(&input)), expected);
+        // a tiny buffer splits levels and line endings across reads.
+        for capacity in [1, 2, 3, 7] {
+            let reader = io::BufReader::with_capacity(capacity, generated.text.as_bytes());
+            assert_eq!(count_stream(reader).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown level")]
+    fn test_generate_rejects_wildcards() {
+        generate("1 2 3\n4 ? 6\n");
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let input = generate_partial("1 ? 3\n? 5 ?\n1 9 2\n7 6 4 2 1\n");
+        assert_eq!(input[0], vec![Some(1), None, Some(3)]);
+        assert_eq!(
+            count_assignments(&input, 0..=9),
+            vec![
+                AssignmentCounts {
+                    safe: 1,
+                    dampened: 10,
+                },
+                // 2..=4 then 6..=8 either way round; dropping a level leaves 6 choices against
+                // any of 10, twice over less the 36 in both, and dropping the 5 adds 0 1 and 1 0.
+                AssignmentCounts {
+                    safe: 18,
+                    dampened: 86,
+                },
+                AssignmentCounts {
+                    safe: 0,
+                    dampened: 1,
+                },
+                AssignmentCounts {
+                    safe: 1,
+                    dampened: 1,
+                },
+            ]
+        );
+        assert_eq!(safe_assignments(&input[0], 0..=9), vec![vec![1, 2, 3]]);
+        assert_eq!(safe_assignments(&[None, Some(1)], 0..=9).len(), 4);
     }
 
     #[test]
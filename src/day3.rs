@@ -1,9 +1,19 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::{Range, RangeInclusive};
+
+use crate::rng::Rng;
+
+use console::style;
+use num::BigUint;
+
 use winnow::{
-    ascii::dec_uint,
-    combinator::{alt, opt, repeat, separated_foldl1, seq},
-    error::ContextError,
-    token::any,
-    Parser as _,
+    ascii::{dec_uint, digit0},
+    combinator::{alt, delimited, repeat, separated, seq},
+    error::{ContextError, PResult},
+    stream::Stream as _,
+    token::{any, one_of},
+    Parser,
 };
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -12,6 +22,13 @@ pub struct Mul {
     y: u32,
 }
 
+impl Mul {
+    /// x * y, without overflowing when added up over a large input.
+    pub fn product(&self) -> usize {
+        self.x as usize * self.y as usize
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Op {
     Mul(Mul),
@@ -23,8 +40,8 @@ pub fn generate(input: &str) -> &str {
     input
 }
 
-pub fn parse_ops(input: &str) -> Vec<Option<Op>> {
-    let op = alt((
+fn op(input: &mut &str) -> PResult<Op> {
+    alt((
         seq! {
                 Mul {
                     _: "mul(",
@@ -34,35 +51,107 @@ pub fn parse_ops(input: &str) -> Vec<Option<Op>> {
                     _: ")",
             }
         }
-        .map(|m| Some(Op::Mul(m))),
-        "do()".map(|_| Some(Op::Do)),
-        "don't()".map(|_| Some(Op::Dont)),
-        any.map(|_| None),
-    ));
-    repeat(0.., op).parse(input).unwrap()
+        .map(Op::Mul),
+        "do()".value(Op::Do),
+        "don't()".value(Op::Dont),
+    ))
+    .parse_next(input)
+}
+
+pub fn parse_ops(input: &str) -> Vec<Option<Op>> {
+    repeat(0.., alt((op.map(Some), any.value(None))))
+        .parse(input)
+        .unwrap()
+}
+
+/// The ops in corrupted memory with their spans, parsed only as they are asked for.
+pub struct OpIter<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> OpIter<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { input, offset: 0 }
+    }
+}
+
+impl Iterator for OpIter<'_> {
+    type Item = (Op, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(c) = self.input[self.offset..].chars().next() {
+            let start = self.offset;
+            match op.parse_peek(&self.input[start..]) {
+                Ok((rest, op)) => {
+                    self.offset = self.input.len() - rest.len();
+                    return Some((op, start..self.offset));
+                }
+                Err(_) => self.offset += c.len_utf8(),
+            }
+        }
+        None
+    }
+}
+
+/// As parse_ops, keeping only the ops, each with the bytes of the input it was parsed from.
+pub fn parse_spanned_ops(input: &str) -> Vec<(Op, Range<usize>)> {
+    OpIter::new(input).collect()
+}
+
+/// The input with muls that count in bold green, disabled muls dimmed, and do()s and don't()s
+/// underlined, each op followed by the part 2 total so far. `colour` forces styling on or off.
+pub fn render(input: &str, colour: bool) -> String {
+    let mut out = String::new();
+    let (mut total, mut enabled, mut end) = (0, true, 0);
+    for (op, span) in OpIter::new(input) {
+        out.push_str(&input[end..span.start]);
+        let text = style(&input[span.clone()]);
+        let text = match op {
+            Op::Mul(m) if enabled => {
+                total += m.x as usize * m.y as usize;
+                text.green().bold()
+            }
+            Op::Mul(_) => text.dim(),
+            Op::Do => {
+                enabled = true;
+                text.cyan().underlined()
+            }
+            Op::Dont => {
+                enabled = false;
+                text.red().underlined()
+            }
+        };
+        out += &text.force_styling(colour).to_string();
+        out += &style(format!("[{total}]"))
+            .dim()
+            .force_styling(colour)
+            .to_string();
+        end = span.end;
+    }
+    out.push_str(&input[end..]);
+    out
 }
 
 pub fn part_1(input: &str) -> usize {
-    parse_ops(input)
-        .into_iter()
-        .filter_map(|o| match o {
-            Some(Op::Mul(m)) => Some(m),
+    OpIter::new(input)
+        .filter_map(|(o, _)| match o {
+            Op::Mul(m) => Some(m),
             _ => None,
         })
-        .fold(0, |acc, m| acc + m.x * m.y) as usize
+        .fold(0, |acc, m| acc + m.product())
 }
 
 pub fn part_2(input: &str) -> usize {
-    parse_ops(input)
-        .into_iter()
-        .filter_map(|o| o)
+    OpIter::new(input)
+        .map(|(o, _)| o)
         .fold((0, true), |(acc, enabled), o| match (enabled, o) {
-            (true, Op::Mul(m)) => (acc + m.x * m.y, enabled),
+            (true, Op::Mul(m)) => (acc + m.product(), enabled),
             (_, Op::Do) => (acc, true),
             (_, Op::Dont) => (acc, false),
             _ => (acc, enabled),
         })
-        .0 as usize
+        .0
 }
 
 pub fn part_1_fold(input: &str) -> usize {
@@ -74,27 +163,1038 @@ pub fn part_1_fold(input: &str) -> usize {
            dec_uint::<_, u32, ContextError>,
            _: ")",
         )
-        .map(|(x, y)| x * y),
-        opt(any).map(|_| 0),
+        .map(|(x, y)| x as usize * y as usize),
+        any.value(0),
+    ));
+    repeat(0.., op)
+        .fold(|| 0, |l, r| l + r)
+        .parse(input)
+        .unwrap()
+}
+
+/// As part 2, folding over the ops as they are parsed and carrying whether muls are enabled.
+pub fn part_2_fold(input: &str) -> usize {
+    let op = alt((
+        seq! (
+           _: "mul(",
+           dec_uint::<_, u32, ContextError>,
+           _: ",",
+           dec_uint::<_, u32, ContextError>,
+           _: ")",
+        )
+        .map(|(x, y)| Some(Op::Mul(Mul { x, y }))),
+        "do()".value(Some(Op::Do)),
+        "don't()".value(Some(Op::Dont)),
+        any.value(None),
+    ));
+    repeat(0.., op)
+        .fold(
+            || (0, true),
+            |(acc, enabled), o| match (enabled, o) {
+                (true, Some(Op::Mul(m))) => (acc + m.product(), enabled),
+                (_, Some(Op::Do)) => (acc, true),
+                (_, Some(Op::Dont)) => (acc, false),
+                _ => (acc, enabled),
+            },
+        )
+        .parse(input)
+        .unwrap()
+        .0
+}
+
+pub fn part_1_scan(input: &str) -> usize {
+    fast_ops(input)
+        .map(|op| match op {
+            Op::Mul(m) => m.product(),
+            _ => 0,
+        })
+        .sum()
+}
+
+pub fn part_2_scan(input: &str) -> usize {
+    fast_ops(input)
+        .fold((0, true), |(acc, enabled), o| match (enabled, o) {
+            (true, Op::Mul(m)) => (acc + m.product(), enabled),
+            (_, Op::Do) => (acc, true),
+            (_, Op::Dont) => (acc, false),
+            _ => (acc, enabled),
+        })
+        .0
+}
+
+/// The ops, found by jumping straight to each `m` or `d` rather than trying every parser at
+/// every byte, and checking the rest of the instruction by hand.
+fn fast_ops(input: &str) -> impl Iterator<Item = Op> + '_ {
+    let input = input.as_bytes();
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        while let Some(i) = input[at..].iter().position(|&b| b == b'm' || b == b'd') {
+            let start = at + i;
+            let rest = &input[start..];
+            at = start + 1;
+            if let Some((m, len)) = rest.strip_prefix(b"mul(").and_then(mul_operands) {
+                at = start + 4 + len;
+                return Some(Op::Mul(m));
+            } else if rest.starts_with(b"do()") {
+                at = start + 4;
+                return Some(Op::Do);
+            } else if rest.starts_with(b"don't()") {
+                at = start + 7;
+                return Some(Op::Dont);
+            }
+        }
+        None
+    })
+}
+
+/// `x,y)` and how many bytes it took.
+fn mul_operands(input: &[u8]) -> Option<(Mul, usize)> {
+    let (x, i) = operand(input)?;
+    let (y, j) = operand(input[i..].strip_prefix(b",")?)?;
+    (input.get(i + 1 + j) == Some(&b')')).then_some((Mul { x, y }, i + j + 2))
+}
+
+/// A u32 as dec_uint reads it, with no leading zeros, and how many bytes it took.
+fn operand(input: &[u8]) -> Option<(u32, usize)> {
+    match input.first()? {
+        b'0' => Some((0, 1)),
+        b'1'..=b'9' => {
+            let len = input.iter().take_while(|b| b.is_ascii_digit()).count();
+            input[..len]
+                .iter()
+                .try_fold(0u32, |n, &d| {
+                    n.checked_mul(10)?.checked_add((d - b'0') as u32)
+                })
+                .map(|n| (n, len))
+        }
+        _ => None,
+    }
+}
+
+pub fn part_1_big(input: &str) -> BigUint {
+    totals_big(input, Some(3)).0
+}
+
+pub fn part_2_big(input: &str) -> BigUint {
+    totals_big(input, Some(3)).1
+}
+
+#[derive(Clone)]
+enum BigOp {
+    Mul(BigUint),
+    Enable(bool),
+    Junk,
+}
+
+/// An operand of any size, with no leading zeros, as dec_uint would accept. Operands of more
+/// than `max_digits` digits don't parse.
+fn big_operand<'i>(max_digits: Option<usize>) -> impl Parser<&'i str, BigUint, ContextError> {
+    alt(("0", (one_of('1'..='9'), digit0).take()))
+        .verify(move |digits: &str| max_digits.is_none_or(|max| digits.len() <= max))
+        .map(|digits: &str| digits.parse().unwrap())
+}
+
+/// The part 1 and part 2 totals in arbitrary precision, with operands of up to `max_digits`
+/// digits; the puzzle's are 1 to 3. A mul with a longer operand is junk.
+pub fn totals_big(input: &str, max_digits: Option<usize>) -> (BigUint, BigUint) {
+    let op = alt((
+        seq! (
+           _: "mul(",
+           big_operand(max_digits),
+           _: ",",
+           big_operand(max_digits),
+           _: ")",
+        )
+        .map(|(x, y)| BigOp::Mul(x * y)),
+        "do()".value(BigOp::Enable(true)),
+        "don't()".value(BigOp::Enable(false)),
+        any.value(BigOp::Junk),
     ));
-    separated_foldl1(op, any, |l, _, r| l + r)
+    let (part_1, part_2, _) = repeat(0.., op)
+        .fold(
+            || (BigUint::ZERO, BigUint::ZERO, true),
+            |(mut part_1, mut part_2, enabled), op| match op {
+                BigOp::Mul(product) => {
+                    if enabled {
+                        part_2 += &product;
+                    }
+                    part_1 += product;
+                    (part_1, part_2, enabled)
+                }
+                BigOp::Enable(enabled) => (part_1, part_2, enabled),
+                BigOp::Junk => (part_1, part_2, enabled),
+            },
+        )
         .parse(input)
-        .unwrap() as usize
+        .unwrap();
+    (part_1, part_2)
+}
+
+/// The state an [`InstructionSet`] runs against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Machine {
+    /// Wide enough for sums of products of two u32 arguments.
+    pub acc: i128,
+    pub enabled: bool,
+    pub registers: Vec<i128>,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self {
+            acc: 0,
+            enabled: true,
+            registers: vec![],
+        }
+    }
+}
+
+impl Machine {
+    /// A register, which starts out as 0.
+    pub fn register(&mut self, r: u32) -> &mut i128 {
+        let r = r as usize;
+        if r >= self.registers.len() {
+            self.registers.resize(r + 1, 0);
+        }
+        &mut self.registers[r]
+    }
+}
+
+/// An operation written as `name(arg,...)` with decimal arguments.
+pub struct Instruction {
+    pub name: &'static str,
+    /// How many arguments make a valid call; any other count is junk.
+    pub arity: RangeInclusive<usize>,
+    pub exec: fn(&mut Machine, &[u32]),
+}
+
+/// A recognised instruction: its index in the set, and its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub op: usize,
+    pub args: Vec<u32>,
+}
+
+/// The instructions to pick out of corrupted memory. Where names overlap, the first registered
+/// that parses wins.
+#[derive(Default)]
+pub struct InstructionSet {
+    instructions: Vec<Instruction>,
+}
+
+impl InstructionSet {
+    pub fn with(
+        mut self,
+        name: &'static str,
+        arity: RangeInclusive<usize>,
+        exec: fn(&mut Machine, &[u32]),
+    ) -> Self {
+        self.instructions.push(Instruction { name, arity, exec });
+        self
+    }
+
+    /// `mul(x,y)` adds its product while enabled.
+    pub fn part_1() -> Self {
+        Self::default().with("mul", 2..=2, |m, args| {
+            if m.enabled {
+                m.acc += args.iter().map(|&a| a as i128).product::<i128>();
+            }
+        })
+    }
+
+    /// As part 1, with `do()` and `don't()` switching `mul` on and off.
+    pub fn part_2() -> Self {
+        Self::part_1()
+            .with("do", 0..=0, |m, _| m.enabled = true)
+            .with("don't", 0..=0, |m, _| m.enabled = false)
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// One instruction, or a junk byte.
+    fn call(&self, input: &mut &str) -> PResult<Option<Call>> {
+        for (op, instruction) in self.instructions.iter().enumerate() {
+            let start = input.checkpoint();
+            let args = delimited(
+                (instruction.name, "("),
+                separated::<_, _, Vec<u32>, _, _, _, _>(0.., dec_uint::<_, u32, ContextError>, ","),
+                ")",
+            )
+            .parse_next(input);
+            match args {
+                Ok(args) if instruction.arity.contains(&args.len()) => {
+                    return Ok(Some(Call { op, args }))
+                }
+                _ => input.reset(&start),
+            }
+        }
+        any.value(None).parse_next(input)
+    }
+
+    pub fn parse(&self, input: &str) -> Vec<Call> {
+        repeat(0.., |i: &mut &str| self.call(i))
+            .fold(Vec::new, |mut calls, call| {
+                calls.extend(call);
+                calls
+            })
+            .parse(input)
+            .unwrap()
+    }
+
+    pub fn run(&self, input: &str) -> Machine {
+        repeat(0.., |i: &mut &str| self.call(i))
+            .fold(Machine::default, |mut machine, call| {
+                if let Some(call) = call {
+                    (self.instructions[call.op].exec)(&mut machine, &call.args);
+                }
+                machine
+            })
+            .parse(input)
+            .unwrap()
+    }
+}
+
+pub fn part_1_vm(input: &str) -> usize {
+    InstructionSet::part_1().run(input).acc as usize
+}
+
+pub fn part_2_vm(input: &str) -> usize {
+    InstructionSet::part_2().run(input).acc as usize
+}
+
+/// Where a [`Scanner`] is in the instruction it may be part way through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Scan {
+    #[default]
+    Idle,
+    /// How much of a literal has matched so far.
+    Literal(&'static [u8], usize),
+    X(Digits),
+    Y(u32, Digits),
+}
+
+/// An operand as read so far, with the same rules as dec_uint: no leading zeros, and no more
+/// than fits in a u32.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Digits {
+    value: u32,
+    len: usize,
+}
+
+impl Digits {
+    fn push(self, digit: u8) -> Option<Self> {
+        if self.len > 0 && self.value == 0 {
+            return None;
+        }
+        let value = self
+            .value
+            .checked_mul(10)?
+            .checked_add((digit - b'0') as u32)?;
+        Some(Self {
+            value,
+            len: self.len + 1,
+        })
+    }
+}
+
+/// Scans corrupted memory a chunk at a time, carrying any part matched instruction and the
+/// enable state between chunks, and totals both parts as it goes.
+///
+/// None of the bytes inside an instruction can start another, so when one fails to match the
+/// scan resumes at the byte that broke it, just as the parser would.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scanner {
+    state: Scan,
+    enabled: bool,
+    part_1: usize,
+    part_2: usize,
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Self {
+            state: Scan::Idle,
+            enabled: true,
+            part_1: 0,
+            part_2: 0,
+        }
+    }
+}
+
+impl Scanner {
+    pub fn feed(&mut self, chunk: &[u8]) {
+        chunk.iter().for_each(|&b| self.step(b));
+    }
+
+    /// The part 1 and part 2 totals of everything fed so far.
+    pub fn totals(&self) -> (usize, usize) {
+        (self.part_1, self.part_2)
+    }
+
+    fn step(&mut self, b: u8) {
+        self.state = match (self.state, b) {
+            (Scan::Literal(text, n), b) if text[n] == b => match (text, n + 1 == text.len()) {
+                (_, false) => Scan::Literal(text, n + 1),
+                (b"mul(", true) => Scan::X(Digits::default()),
+                (b"do()", true) => {
+                    self.enabled = true;
+                    Scan::Idle
+                }
+                _ => {
+                    self.enabled = false;
+                    Scan::Idle
+                }
+            },
+            (Scan::Literal(b"do()", 2), b'n') => Scan::Literal(b"don't()", 3),
+            (Scan::X(x), b',') if x.len > 0 => Scan::Y(x.value, Digits::default()),
+            (Scan::X(x), b'0'..=b'9') => x.push(b).map_or(Scan::Idle, Scan::X),
+            (Scan::Y(x, y), b')') if y.len > 0 => {
+                let product = x as usize * y.value as usize;
+                self.part_1 += product;
+                if self.enabled {
+                    self.part_2 += product;
+                }
+                Scan::Idle
+            }
+            (Scan::Y(x, y), b'0'..=b'9') => y.push(b).map_or(Scan::Idle, |y| Scan::Y(x, y)),
+            (_, b'm') => Scan::Literal(b"mul(", 1),
+            (_, b'd') => Scan::Literal(b"do()", 1),
+            _ => Scan::Idle,
+        }
+    }
+}
+
+/// The part 1 and part 2 totals of the corrupted memory in `input`, read a buffer at a time.
+pub fn scan_reader(mut input: impl Read) -> io::Result<(usize, usize)> {
+    let mut scanner = Scanner::default();
+    let mut buffer = [0; 8192];
+    loop {
+        match input.read(&mut buffer) {
+            Ok(0) => return Ok(scanner.totals()),
+            Ok(n) => scanner.feed(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Corrupted memory with the answers it was generated to have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedMemory {
+    pub text: String,
+    pub part_1: usize,
+    pub part_2: usize,
+}
+
+/// Junk that is nearly an instruction. Each is followed by a separator, so the next piece
+/// can't complete it.
+const NEAR_MISSES: &[&str] = &[
+    "mul(1 ,2)",
+    "mul( 1,2)",
+    "mul[3,4]",
+    "mul(12,3",
+    "mul(4*",
+    "mul(-1,2)",
+    "mul(1,2,3)",
+    "mul(,2)",
+    "mul ( 2,3)",
+    "MUL(2,3)",
+    "do_not()",
+    "don't ()",
+    "do( )",
+    "dont()",
+];
+
+/// Filler with no `m` or `d` to start an instruction.
+const FILLER: &[u8] = b"x%&!@^*+-[]{}<>?:;'0123456789ul ,()";
+
+/// A seeded generator of corrupted memory.
+///
+/// Memory is a run of pieces: a valid `mul`, `do()` or `don't()`, a near miss, a nested
+/// `mul(mul(x,y),z)` where only the inner `mul` counts, or filler. Valid pieces may sit
+/// right next to each other.
+pub struct MemoryGenerator {
+    rng: Rng,
+}
+
+impl MemoryGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// A puzzle operand: 1 to 3 digits, with no leading zeros.
+    fn operand(&mut self) -> u32 {
+        let digits = 1 + self.rng.below(3) as u32;
+        let low = 10u32.pow(digits - 1);
+        low + self.rng.below((10 * low - low) as usize) as u32
+    }
+
+    fn separator(&mut self) -> char {
+        b"x%&!@^*+ "[self.rng.below(9)] as char
+    }
+
+    pub fn memory(&mut self, pieces: usize) -> GeneratedMemory {
+        let mut memory = GeneratedMemory {
+            text: String::new(),
+            part_1: 0,
+            part_2: 0,
+        };
+        let mut enabled = true;
+        for _ in 0..pieces {
+            let mul = |memory: &mut GeneratedMemory, x: u32, y: u32| {
+                memory.part_1 += x as usize * y as usize;
+                if enabled {
+                    memory.part_2 += x as usize * y as usize;
+                }
+            };
+            match self.rng.below(10) {
+                0..=2 => {
+                    let (x, y) = (self.operand(), self.operand());
+                    memory.text += &format!("mul({x},{y})");
+                    mul(&mut memory, x, y);
+                }
+                3 => {
+                    let (x, y, z) = (self.operand(), self.operand(), self.operand());
+                    memory.text += &format!("mul(mul({x},{y}),{z})");
+                    mul(&mut memory, x, y);
+                }
+                4 => {
+                    memory.text += "do()";
+                    enabled = true;
+                }
+                5 => {
+                    memory.text += "don't()";
+                    enabled = false;
+                }
+                6 | 7 => {
+                    memory.text += NEAR_MISSES[self.rng.below(NEAR_MISSES.len())];
+                    let separator = self.separator();
+                    memory.text.push(separator);
+                }
+                _ => {
+                    for _ in 0..1 + self.rng.below(6) {
+                        memory
+                            .text
+                            .push(FILLER[self.rng.below(FILLER.len())] as char);
+                    }
+                }
+            }
+        }
+        memory
+    }
+}
+
+/// Why text that looks like an instruction isn't one by the puzzle's rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    Case,
+    Whitespace,
+    /// Opened or closed with something other than parentheses.
+    Brackets,
+    MissingClose,
+    /// The number of arguments given.
+    Arguments(usize),
+    /// An argument that isn't a number.
+    Operand(String),
+    TooManyDigits,
+    LeadingZero,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Case => write!(f, "name is not lower case"),
+            Reason::Whitespace => write!(f, "whitespace inside the instruction"),
+            Reason::Brackets => write!(f, "brackets are not parentheses"),
+            Reason::MissingClose => write!(f, "no closing bracket"),
+            Reason::Arguments(n) => write!(f, "wrong number of arguments: {n}"),
+            Reason::Operand(operand) => write!(f, "operand {operand:?} is not a number"),
+            Reason::TooManyDigits => write!(f, "operand has more than 3 digits"),
+            Reason::LeadingZero => write!(f, "operand has a leading zero"),
+        }
+    }
+}
+
+/// Text that almost forms an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearMiss {
+    pub span: Range<usize>,
+    pub reasons: Vec<Reason>,
+    /// Whether the parser takes it as an instruction anyway.
+    pub accepted: bool,
+}
+
+/// Find the near misses in the input: an instruction name in any case, followed by a bracket,
+/// read leniently up to its closing bracket, and reported when it breaks the puzzle's rules.
+/// Operands of 1 to 3 digits are the puzzle's, so longer ones the parser takes are reported
+/// as accepted.
+pub fn lint(input: &str) -> Vec<NearMiss> {
+    (0..input.len())
+        .filter_map(|start| near_miss(input, start))
+        .collect()
+}
+
+/// Each near miss on a line: its span, its text and why it was rejected.
+pub fn lint_report(input: &str) -> String {
+    lint(input)
+        .into_iter()
+        .map(|miss| {
+            let reasons = miss.reasons.iter().map(Reason::to_string);
+            format!(
+                "{:?} {:?}: {}{}\n",
+                miss.span,
+                &input[miss.span.clone()],
+                reasons.collect::<Vec<_>>().join("; "),
+                if miss.accepted { " (but accepted)" } else { "" }
+            )
+        })
+        .collect()
 }
 
-pub fn part_2_fold(_input: &str) -> usize {
-    0
-    // parse_ops(input)
-    //     .into_iter()
-    //     .filter_map(|o| o)
-    //     .fold((0, true), |(acc, enabled), o| match (enabled, o) {
-    //         (true, Op::Mul(m)) => (acc + m.x * m.y, enabled),
-    //         (_, Op::Do) => (acc, true),
-    //         (_, Op::Dont) => (acc, false),
-    //         _ => (acc, enabled),
-    //     })
-    //     .0 as usize
+fn near_miss(input: &str, start: usize) -> Option<NearMiss> {
+    let rest = &input.as_bytes()[start..];
+    let mut reasons = vec![];
+    let mut flag = |reason: Reason| {
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+        }
+    };
+    let (name, arity) = [("don't", 0), ("do", 0), ("mul", 2)]
+        .into_iter()
+        .find(|(name, _)| {
+            rest.get(..name.len())
+                .is_some_and(|n| n.eq_ignore_ascii_case(name.as_bytes()))
+        })?;
+    if &rest[..name.len()] != name.as_bytes() {
+        flag(Reason::Case);
+    }
+    let mut at = name.len();
+    let spaces = |at: &mut usize| {
+        let n = rest[*at..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+        *at += n;
+        n > 0
+    };
+    if spaces(&mut at) {
+        flag(Reason::Whitespace);
+    }
+    let close = match rest.get(at)? {
+        b'(' => b')',
+        b'[' => b']',
+        b'{' => b'}',
+        b'<' => b'>',
+        _ => return None,
+    };
+    if close != b')' {
+        flag(Reason::Brackets);
+    }
+    at += 1;
+    let is_close = |b: Option<&u8>| b.is_some_and(|b| b")]}>".contains(b));
+    let mut args = vec![];
+    let closed = loop {
+        if spaces(&mut at) {
+            flag(Reason::Whitespace);
+        }
+        let len = rest[at..]
+            .iter()
+            .take_while(|b| b.is_ascii_alphanumeric() || b"+-*.".contains(b))
+            .count();
+        let token = &input[start + at..start + at + len];
+        at += len;
+        if spaces(&mut at) {
+            flag(Reason::Whitespace);
+        }
+        if !(token.is_empty() && args.is_empty() && is_close(rest.get(at))) {
+            args.push(token);
+        }
+        match rest.get(at) {
+            Some(b',') if args.len() < 8 => at += 1,
+            b if is_close(b) => {
+                if b != Some(&close) {
+                    flag(Reason::Brackets);
+                }
+                at += 1;
+                break true;
+            }
+            _ => break false,
+        }
+    };
+    if !closed {
+        flag(Reason::MissingClose);
+    }
+    if args.len() != arity {
+        flag(Reason::Arguments(args.len()));
+    }
+    for arg in args {
+        if arg.is_empty() || !arg.bytes().all(|b| b.is_ascii_digit()) {
+            flag(Reason::Operand(arg.to_string()));
+        } else if arg.len() > 3 {
+            flag(Reason::TooManyDigits);
+        } else if arg.len() > 1 && arg.starts_with('0') {
+            flag(Reason::LeadingZero);
+        }
+    }
+    if reasons.is_empty() {
+        return None;
+    }
+    let span = start..start + at;
+    let accepted = op
+        .parse_peek(&input[span.clone()])
+        .is_ok_and(|(rest, _)| rest.is_empty());
+    Some(NearMiss {
+        span,
+        reasons,
+        accepted,
+    })
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const INPUT_1: &str = "xmul(2,4)%&mul[3,7]!@^do_not_mul(5,5)+mul(32,64]then(mul(11,8)mul(8,5))";
+    const INPUT_2: &str =
+        "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+
+    #[test]
+    fn test_examples() {
+        assert_eq!(part_1(INPUT_1), 161);
+        assert_eq!(part_2(INPUT_2), 48);
+        assert_eq!(part_2_fold(INPUT_2), 48);
+        assert_eq!(part_2_fold(INPUT_1), 161);
+        assert_eq!(part_1_fold(INPUT_1), 161);
+        assert_eq!(part_2_fold(""), 0);
+        assert_eq!(part_1_vm(INPUT_1), 161);
+        assert_eq!(part_2_vm(INPUT_2), 48);
+        assert_eq!(part_1_scan(INPUT_1), 161);
+        assert_eq!(part_2_scan(INPUT_2), 48);
+        assert_eq!(
+            part_1_scan("mul(05,1)mul(0,5)mul(4294967296,1)mul(4294967295,1)mul(7,8"),
+            4294967295
+        );
+        let max = "mul(4294967295,4294967295)";
+        for part in [part_1, part_1_fold, part_1_vm, part_1_scan, part_2_vm] {
+            assert_eq!(part(max), 18446744065119617025);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_op_iter() {
+        let input = MemoryGenerator::new(1).memory(1_000_000).text;
+        let start = std::time::Instant::now();
+        let ops = parse_ops(&input);
+        eprintln!(
+            "parse_ops: {:?}, {} bytes",
+            start.elapsed(),
+            ops.capacity() * std::mem::size_of::<Option<Op>>()
+        );
+        let start = std::time::Instant::now();
+        let count = OpIter::new(&input).count();
+        eprintln!("OpIter: {:?}, no allocation", start.elapsed());
+        assert_eq!(ops.into_iter().flatten().count(), count);
+        let start = std::time::Instant::now();
+        let totals = (part_1(&input), part_2(&input));
+        eprintln!("part_1 and part_2: {:?}", start.elapsed());
+        let start = std::time::Instant::now();
+        assert_eq!((part_1_vm(&input), part_2_vm(&input)), totals);
+        eprintln!("part_1_vm and part_2_vm: {:?}", start.elapsed());
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_scanners() {
+        let generated = MemoryGenerator::new(1).memory(1_000_000);
+        let input = generated.text;
+        eprintln!("{} bytes", input.len());
+        let variants = [
+            ("part_1", part_1 as fn(&str) -> usize),
+            ("part_1_fold", part_1_fold),
+            ("part_1_scan", part_1_scan),
+            ("part_2", part_2),
+            ("part_2_fold", part_2_fold),
+            ("part_2_scan", part_2_scan),
+        ];
+        for (name, variant) in variants {
+            let start = std::time::Instant::now();
+            let total = variant(&input);
+            eprintln!("{name}: {total} in {:?}", start.elapsed());
+        }
+        assert_eq!(part_1_scan(&input), generated.part_1);
+        assert_eq!(part_2_scan(&input), generated.part_2);
+    }
+
+    #[test]
+    fn test_big() {
+        assert_eq!(part_1_big(INPUT_1), BigUint::from(161u32));
+        assert_eq!(part_2_big(INPUT_2), BigUint::from(48u32));
+        let input = "mul(123456789012,10)don't()mul(4294967296,2)mul(1234,5)mul(05,1)";
+        // the operands that overflow a u32 make junk of their muls.
+        assert_eq!(part_1(input), 6170);
+        let expected: BigUint = "1234567890120".parse().unwrap();
+        assert_eq!(
+            totals_big(input, None),
+            (&expected + BigUint::from(8589934592u64 + 6170), expected)
+        );
+        assert_eq!(
+            totals_big(input, Some(10)),
+            (BigUint::from(8589934592u64 + 6170), BigUint::ZERO)
+        );
+        assert_eq!(totals_big(input, Some(3)), (BigUint::ZERO, BigUint::ZERO));
+    }
+
+    #[test]
+    fn test_spans() {
+        assert_eq!(
+            OpIter::new("ümul(1,2)é").collect::<Vec<_>>(),
+            [(Op::Mul(Mul { x: 1, y: 2 }), 2..10)]
+        );
+        let ops = parse_spanned_ops(INPUT_2);
+        assert_eq!(ops[0], (Op::Mul(Mul { x: 2, y: 4 }), 1..9));
+        assert_eq!(&INPUT_2[ops[1].1.clone()], "don't()");
+        assert_eq!(
+            render(INPUT_2, false),
+            "xmul(2,4)[8]&mul[3,7]!^don't()[8]_mul(5,5)[8]+mul(32,64](mul(11,8)[8]undo()[8]?mul(8,5)[48])"
+        );
+        let coloured = render("mul(2,4)", true);
+        assert!(coloured.contains("\u{1b}[") && coloured.contains("mul(2,4)"));
+    }
+
+    #[test]
+    fn test_scanner_splits() {
+        for input in [
+            INPUT_1,
+            INPUT_2,
+            "mmul(1,2)dmul(3,4)ddo()don't()do(mul(05,1)mul(0,5)mul(4294967296,1)mul(7,8",
+        ] {
+            let expected = (part_1(input), part_2(input));
+            for split in 0..=input.len() {
+                let mut scanner = Scanner::default();
+                scanner.feed(&input.as_bytes()[..split]);
+                scanner.feed(&input.as_bytes()[split..]);
+                assert_eq!(scanner.totals(), expected, "{input:?} at {split}");
+            }
+            for size in 1..8 {
+                let reader = Chunked(input.as_bytes(), size);
+                assert_eq!(scan_reader(reader).unwrap(), expected);
+            }
+        }
+    }
+
+    /// Reads at most `.1` bytes at a time.
+    struct Chunked<'a>(&'a [u8], usize);
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_vm_extensions() {
+        let set = InstructionSet::part_2()
+            .with("add", 2..=2, |m, args| m.acc += (args[0] + args[1]) as i128)
+            .with("sub", 2..=2, |m, args| {
+                m.acc += args[0] as i128 - args[1] as i128
+            })
+            .with("reset", 0..=0, |m, _| m.acc = 0)
+            .with("set", 2..=2, |m, args| {
+                *m.register(args[0]) = args[1] as i128
+            })
+            .with("acc", 1..=1, |m, args| m.acc += *m.register(args[0]))
+            .with("mul", 3..=usize::MAX, |m, args| {
+                m.acc += args.iter().map(|&a| a as i128).product::<i128>()
+            });
+        let input = "add(1,2)sub(1,10)reset()mul(2,3)set(1,5)acc(1)mul(2,3,4)mul(1)don't()mul(9,9)";
+        assert_eq!(
+            set.parse(input)
+                .iter()
+                .map(|c| set.instructions()[c.op].name)
+                .collect::<Vec<_>>(),
+            ["add", "sub", "reset", "mul", "set", "acc", "mul", "don't", "mul"]
+        );
+        // mul(1) is junk, and after don't() mul(9,9) is still the two argument mul.
+        let machine = set.run(input);
+        assert_eq!(machine.acc, 6 + 5 + 24);
+        assert_eq!(machine.registers, [0, 5]);
+        assert!(!machine.enabled);
+    }
+
+    fn arb_memory() -> impl Strategy<Value = String> {
+        prop::collection::vec(
+            prop_oneof![
+                "mul\\([0-9]{1,3},[0-9]{1,3}\\)",
+                "do\\(\\)",
+                "don't\\(\\)",
+                "[mudlon'(),0-9 x]{0,4}"
+            ],
+            0..20,
+        )
+        .prop_map(|parts| parts.concat())
+    }
+
+    #[test]
+    fn test_lint() {
+        use Reason::*;
+        for (text, reasons) in [
+            ("mul(1 ,2)", vec![Whitespace]),
+            ("mul ( 2,3)", vec![Whitespace]),
+            ("mul[3,4]", vec![Brackets]),
+            ("mul(3,4]", vec![Brackets]),
+            ("mul(12,3", vec![MissingClose]),
+            (
+                "mul(4*",
+                vec![MissingClose, Arguments(1), Operand("4*".into())],
+            ),
+            ("mul(-1,2)", vec![Operand("-1".into())]),
+            ("mul(1,2,3)", vec![Arguments(3)]),
+            ("mul(,2)", vec![Operand("".into())]),
+            ("mul()", vec![Arguments(0)]),
+            ("MUL(2,3)", vec![Case]),
+            ("mul(05,1)", vec![LeadingZero]),
+            ("don't ()", vec![Whitespace]),
+            ("do( )", vec![Whitespace]),
+            ("do(1)", vec![Arguments(1)]),
+        ] {
+            let expected = NearMiss {
+                span: 0..text.len(),
+                reasons,
+                accepted: false,
+            };
+            assert_eq!(lint(text), [expected], "{text:?}");
+        }
+        assert_eq!(
+            lint("mul(1234,5)"),
+            [NearMiss {
+                span: 0..11,
+                reasons: vec![TooManyDigits],
+                accepted: true,
+            }]
+        );
+        assert_eq!(
+            lint_report(INPUT_2),
+            "10..18 \"mul[3,7]\": brackets are not parentheses\n\
+             37..47 \"mul(32,64]\": brackets are not parentheses\n"
+        );
+        for text in [
+            "mul(1,2)", "do()", "don't()", "do_not()", "dont()", "mul 1,2",
+        ] {
+            assert_eq!(lint(text), [], "{text:?}");
+        }
+        // only the outer mul misses: the inner one is an instruction.
+        assert_eq!(
+            lint("mul(mul(2,3),4)"),
+            [NearMiss {
+                span: 0..7,
+                reasons: vec![MissingClose, Arguments(1), Operand("mul".into())],
+                accepted: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_generated_memory() {
+        let generated = MemoryGenerator::new(7).memory(200);
+        assert!(generated.part_2 < generated.part_1);
+        assert!(generated.text.contains(")mul("));
+        assert!(generated.text.contains("mul(mul("));
+        // a fixed seed always makes the same memory.
+        assert_eq!(MemoryGenerator::new(7).memory(200), generated);
+    }
+
+    proptest! {
+        #[test]
+        fn test_variants_match_generated(seed in prop::arbitrary::any::<u64>(), pieces in 0usize..60) {
+            let GeneratedMemory { text, part_1: p1, part_2: p2 } = MemoryGenerator::new(seed).memory(pieces);
+            let input = generate(&text);
+            for (name, variant) in [
+                ("part_1", part_1 as fn(&str) -> usize),
+                ("part_1_fold", part_1_fold),
+                ("part_1_vm", part_1_vm),
+                ("part_1_scan", part_1_scan),
+            ] {
+                prop_assert_eq!(variant(input), p1, "{} on {:?}", name, text);
+            }
+            for (name, variant) in [
+                ("part_2", part_2 as fn(&str) -> usize),
+                ("part_2_fold", part_2_fold),
+                ("part_2_vm", part_2_vm),
+                ("part_2_scan", part_2_scan),
+            ] {
+                prop_assert_eq!(variant(input), p2, "{} on {:?}", name, text);
+            }
+            prop_assert_eq!(part_1_big(input), BigUint::from(p1));
+            prop_assert_eq!(part_2_big(input), BigUint::from(p2));
+            prop_assert_eq!(scan_reader(Chunked(text.as_bytes(), 3)).unwrap(), (p1, p2));
+        }
+
+        #[test]
+        fn test_part_2_fold_matches(input in arb_memory()) {
+            prop_assert_eq!(part_2_fold(&input), part_2(&input));
+        }
+
+        #[test]
+        fn test_scanner_matches(input in arb_memory(), splits in prop::collection::vec(prop::arbitrary::any::<prop::sample::Index>(), 0..4)) {
+            let mut splits = splits.iter().map(|i| i.index(input.len() + 1)).collect::<Vec<_>>();
+            splits.push(0);
+            splits.push(input.len());
+            splits.sort();
+            let mut scanner = Scanner::default();
+            splits.windows(2).for_each(|w| scanner.feed(&input.as_bytes()[w[0]..w[1]]));
+            prop_assert_eq!(scanner.totals(), (part_1(&input), part_2(&input)));
+        }
+
+        #[test]
+        fn test_spans_match(input in arb_memory()) {
+            let spanned = parse_spanned_ops(&input);
+            let ops = parse_ops(&input).into_iter().flatten().collect::<Vec<_>>();
+            prop_assert_eq!(spanned.iter().map(|(op, _)| *op).collect::<Vec<_>>(), ops);
+            for (op, span) in spanned {
+                prop_assert_eq!(parse_ops(&input[span]), vec![Some(op)]);
+            }
+        }
+
+        #[test]
+        fn test_big_matches(input in arb_memory()) {
+            // 9 digits always fit in a u32, and so does the total of these small inputs.
+            let (part_1_big, part_2_big) = totals_big(&input, Some(9));
+            prop_assert_eq!(part_1_big, BigUint::from(part_1(&input)));
+            prop_assert_eq!(part_2_big, BigUint::from(part_2(&input)));
+        }
+
+        #[test]
+        fn test_scan_matches(input in arb_memory()) {
+            prop_assert_eq!(part_1_scan(&input), part_1(&input));
+            prop_assert_eq!(part_2_scan(&input), part_2(&input));
+        }
+
+        #[test]
+        fn test_lint_matches_parser(seed in prop::arbitrary::any::<u64>(), pieces in 0usize..60) {
+            let text = MemoryGenerator::new(seed).memory(pieces).text;
+            let misses = lint(&text);
+            // generated instructions keep to the puzzle's rules, so none are near misses.
+            for (_, span) in OpIter::new(&text) {
+                prop_assert!(misses.iter().all(|m| m.span.start != span.start), "{:?}", &text[span]);
+            }
+            for miss in misses {
+                prop_assert!(!miss.accepted, "{:?}", &text[miss.span]);
+            }
+        }
+
+        #[test]
+        fn test_vm_matches(input in arb_memory()) {
+            prop_assert_eq!(part_1_vm(&input), part_1(&input));
+            prop_assert_eq!(part_2_vm(&input), part_2(&input));
+        }
+    }
+}
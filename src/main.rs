@@ -6,12 +6,16 @@ mod day5;
 mod day6;
 mod day7;
 mod day8;
+mod rng;
 
 aoc_main::main! {
     year 2024;
     day1 : generate => part_1, part_2;
-    day2 : generate => part_1, part_2;
-    day3 : generate => part_1, part_2, part_1_fold, part_2_fold;
+    day1 : generate_bytes => part_1_columns, part_2_columns;
+    day2 : generate => part_1, part_2, part_2_single_pass, part_1_dp, part_2_dp,
+        part_1_rayon, part_2_rayon, part_1_flat, part_2_flat;
+    day3 : generate => part_1, part_2, part_1_fold, part_2_fold, part_1_vm, part_2_vm,
+        part_1_big, part_2_big, part_1_scan, part_2_scan;
     day4 : generate => part_1, part_2, part_1_rayon, part_2_rayon;
     day5 : generate => part_1, part_2, part_1_rayon, part_2_rayon;
     day6 : generate => part_1, part_2;